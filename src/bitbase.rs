@@ -0,0 +1,155 @@
+use crate::bitboard::Square;
+use crate::gamestate::{Side, BLACK, WHITE};
+use crate::movegen::{king_move_bitboard, pawn_attack_bitboard};
+
+/// Both sides to move, both kings and a white pawn on files a-d and ranks 2-7.
+const KPK_POSITIONS: usize = 2 * 64 * 64 * 4 * 6;
+
+/// Results of the retrograde analysis, combined as bit flags while classifying.
+const INVALID: u8 = 0;
+const UNKNOWN: u8 = 1;
+const DRAW: u8 = 2;
+const WIN: u8 = 4;
+
+lazy_static! {
+    /// One bit per position, set if the side with the pawn wins.
+    pub static ref KPK_BITBASE: Vec<u64> = generate_kpk();
+}
+
+/// Whether the side with the pawn wins king and pawn against king, with `strong` the side with the pawn.
+pub fn probe_kpk(strong_king: Square, pawn: Square, weak_king: Square, side_to_move: Side, strong: Side) -> bool {
+    // Normalise to white with the pawn on the queenside.
+    let flip = if strong == WHITE { 0 } else { 56 };
+    let mirror = if (pawn ^ flip) % 8 >= 4 { 7 } else { 0 };
+    let index = kpk_index(side_to_move ^ strong, strong_king ^ flip ^ mirror, weak_king ^ flip ^ mirror, pawn ^ flip ^ mirror);
+    KPK_BITBASE[index / 64] & (1 << (index % 64)) != 0
+}
+
+fn kpk_index(side_to_move: Side, white_king: Square, black_king: Square, pawn: Square) -> usize {
+    side_to_move + 2 * (white_king + 64 * (black_king + 64 * (pawn % 8 + 4 * (pawn / 8 - 1))))
+}
+
+/// Classifies every position, then resolves the unknown ones from their successors until nothing changes.
+fn generate_kpk() -> Vec<u64> {
+    let mut results = vec![INVALID; KPK_POSITIONS];
+    let positions: Vec<(usize, Side, Square, Square, Square)> = (0..KPK_POSITIONS).map(|index| {
+        let pawn_index = index / (2 * 64 * 64);
+        (index, index % 2, (index / 2) % 64, (index / 128) % 64, 8 * (pawn_index / 4 + 1) + pawn_index % 4)
+    }).collect();
+
+    for &(index, side_to_move, white_king, black_king, pawn) in &positions {
+        results[index] = initial_kpk_result(side_to_move, white_king, black_king, pawn);
+    }
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &(index, side_to_move, white_king, black_king, pawn) in &positions {
+            if results[index] == UNKNOWN {
+                results[index] = classify_kpk(&results, side_to_move, white_king, black_king, pawn);
+                changed |= results[index] != UNKNOWN;
+            }
+        }
+    }
+
+    let mut bitbase = vec![0; KPK_POSITIONS / 64];
+    for (index, &result) in results.iter().enumerate() {
+        if result == WIN {
+            bitbase[index / 64] |= 1 << (index % 64);
+        }
+    }
+    bitbase
+}
+
+/// Illegal positions and the ones decided without looking at any move.
+fn initial_kpk_result(side_to_move: Side, white_king: Square, black_king: Square, pawn: Square) -> u8 {
+    let push_square = pawn + 8;
+    if distance(white_king, black_king) <= 1 || white_king == pawn || black_king == pawn
+        || (side_to_move == WHITE && pawn_attack_bitboard(pawn, WHITE).has(black_king)) {
+        return INVALID;
+    }
+    if side_to_move == WHITE && pawn / 8 == 6 && white_king != push_square
+        && (distance(black_king, push_square) > 1 || king_move_bitboard(white_king).has(push_square)) {
+        // The pawn promotes and the queen can't be taken.
+        return WIN;
+    }
+    if side_to_move == BLACK {
+        let guarded = king_move_bitboard(white_king) | pawn_attack_bitboard(pawn, WHITE);
+        let escapes = king_move_bitboard(black_king) & !guarded;
+        if escapes.is_empty() || (escapes.has(pawn) && !king_move_bitboard(white_king).has(pawn)) {
+            // Stalemate, or the pawn is lost.
+            return DRAW;
+        }
+    }
+    UNKNOWN
+}
+
+/// White wins if one move wins, black draws if one move draws, illegal successors count for neither.
+fn classify_kpk(results: &[u8], side_to_move: Side, white_king: Square, black_king: Square, pawn: Square) -> u8 {
+    let mut successors = 0;
+    if side_to_move == WHITE {
+        for to in king_move_bitboard(white_king) {
+            successors |= results[kpk_index(BLACK, to, black_king, pawn)];
+        }
+        if pawn / 8 < 6 {
+            successors |= results[kpk_index(BLACK, white_king, black_king, pawn + 8)];
+        }
+        let push_square = pawn + 8;
+        if pawn / 8 == 1 && push_square != white_king && push_square != black_king {
+            successors |= results[kpk_index(BLACK, white_king, black_king, pawn + 16)];
+        }
+    } else {
+        for to in king_move_bitboard(black_king) {
+            successors |= results[kpk_index(WHITE, white_king, to, pawn)];
+        }
+    }
+
+    let (good, bad) = if side_to_move == WHITE { (WIN, DRAW) } else { (DRAW, WIN) };
+    if successors & good != 0 {
+        good
+    } else if successors & UNKNOWN != 0 {
+        UNKNOWN
+    } else {
+        bad
+    }
+}
+
+fn distance(a: Square, b: Square) -> usize {
+    (a % 8).abs_diff(b % 8).max((a / 8).abs_diff(b / 8))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gamestate::{BLACK, WHITE};
+
+    use super::probe_kpk;
+
+    // Squares as in the FEN diagrams: a1 = 0, h8 = 63.
+    const fn square(name: &[u8; 2]) -> usize {
+        (name[1] - b'1') as usize * 8 + (name[0] - b'a') as usize
+    }
+
+    #[test]
+    fn test_kpk_known_positions() {
+        // Opposition in front of the pawn: won with black to move, drawn with white to move.
+        assert!(probe_kpk(square(b"e5"), square(b"e4"), square(b"e7"), BLACK, WHITE));
+        assert!(!probe_kpk(square(b"e5"), square(b"e4"), square(b"e7"), WHITE, WHITE));
+        // The defending king outside the square of the pawn.
+        assert!(probe_kpk(square(b"a1"), square(b"d5"), square(b"h8"), WHITE, WHITE));
+        assert!(!probe_kpk(square(b"a1"), square(b"d4"), square(b"f6"), BLACK, WHITE));
+        // A rook pawn is drawn once the defending king reaches the corner.
+        assert!(!probe_kpk(square(b"g6"), square(b"h5"), square(b"h8"), WHITE, WHITE));
+        // The attacking king on the sixth rank ahead of its pawn wins regardless of the move.
+        assert!(probe_kpk(square(b"d6"), square(b"d4"), square(b"d8"), WHITE, WHITE));
+        assert!(probe_kpk(square(b"d6"), square(b"d4"), square(b"d8"), BLACK, WHITE));
+    }
+
+    #[test]
+    fn test_kpk_is_symmetric() {
+        // Mirrored files and colours give the same results.
+        assert!(probe_kpk(square(b"d6"), square(b"d4"), square(b"d8"), BLACK, WHITE));
+        assert!(probe_kpk(square(b"e6"), square(b"e4"), square(b"e8"), BLACK, WHITE));
+        assert!(probe_kpk(square(b"d3"), square(b"d5"), square(b"d1"), WHITE, BLACK));
+        assert!(!probe_kpk(square(b"e4"), square(b"e5"), square(b"e2"), BLACK, BLACK));
+    }
+}
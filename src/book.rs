@@ -1,5 +1,4 @@
 use std::collections::HashMap;
-use std::fs::read_to_string;
 
 use crate::book_data;
 
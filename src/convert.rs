@@ -0,0 +1,397 @@
+use std::{
+    env,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, ErrorKind, Lines, Write},
+    path::Path,
+};
+
+use clap::Parser;
+
+use crate::{
+    gamestate::{piece_to_char, GameState, PAWN, WHITE},
+    packed::{shuffle_file, PackedPosition, PackedReader, BLACK_WIN, DRAW, WHITE_WIN},
+    r#move::{CastlingSide, Move},
+    smpsearch::Eval,
+    texel::eval_into_white_viewpoint,
+};
+
+/// Positions held in memory at once while shuffling, 32 bytes each.
+const DEFAULT_SHUFFLE_MEMORY: usize = 1 << 25;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Packed,
+    /// Texel samples, a reduced fen and the result from white's point of view per line.
+    Text,
+    /// `<fen> ce <score>; c9 "<result>"; hmvc <clock>; fmvn <move>;` per line, `[1.0]` style results are read too.
+    Epd,
+    /// Games are read as the positions before every move, written positions become games without moves for inspection.
+    Pgn,
+}
+
+impl Format {
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|extension| extension.to_str()) {
+            Some("bin" | "packed") => Format::Packed,
+            Some("epd") => Format::Epd,
+            Some("pgn") => Format::Pgn,
+            _ => Format::Text,
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+struct ConvertArgs {
+    /// The formats follow the extensions: .bin packed, .epd, .pgn, anything else texel text.
+    #[arg(long)]
+    input: String,
+    #[arg(long)]
+    output: String,
+}
+
+#[derive(Parser, Debug)]
+struct ShuffleArgs {
+    #[arg(long)]
+    input: String,
+    #[arg(long)]
+    output: String,
+    #[arg(long, default_value_t = DEFAULT_SHUFFLE_MEMORY)]
+    memory_positions: usize,
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+/// `convert --input <file> --output <file>`: rewrites positions between the training data formats.
+pub fn convert() {
+    // Skips the program name, clap takes "convert" for it.
+    let args = ConvertArgs::parse_from(env::args().skip(1));
+    match convert_file(&args.input, &args.output) {
+        Ok(count) => println!("Converted {} positions", count),
+        Err(error) => println!("Conversion failed: {}", error),
+    }
+}
+
+/// `shuffle --input <file> --output <file>`: shuffles a packed file.
+pub fn shuffle() {
+    let args = ShuffleArgs::parse_from(env::args().skip(1));
+    match shuffle_file(&args.input, &args.output, args.memory_positions, args.seed) {
+        Ok(()) => println!("Shuffled {} into {}", args.input, args.output),
+        Err(error) => println!("Shuffling failed: {}", error),
+    }
+}
+
+pub fn convert_file(input: &str, output: &str) -> io::Result<usize> {
+    let format = Format::from_path(output);
+    let mut writer = BufWriter::new(File::create(output)?);
+    let mut count = 0;
+    for position in read_positions(input, Format::from_path(input))? {
+        write_position(&mut writer, &position?, format)?;
+        count += 1;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Streams the positions of a file, every position before a move for PGN games.
+pub fn read_positions(path: &str, format: Format) -> io::Result<Box<dyn Iterator<Item = io::Result<PackedPosition>>>> {
+    if format == Format::Packed {
+        return Ok(Box::new(PackedReader::open(path)?));
+    }
+    let lines = BufReader::new(File::open(path)?).lines();
+    Ok(match format {
+        Format::Pgn => Box::new(PgnGames { lines, next_tags: vec![] }.flat_map(|game| {
+            match game.and_then(|game| parse_pgn_game(&game)) {
+                Ok(positions) => positions.into_iter().map(Ok).collect(),
+                Err(error) => vec![Err(error)],
+            }
+        })),
+        _ => Box::new(lines.filter(|line| !matches!(line, Ok(line) if line.trim().is_empty())).map(move |line| {
+            let line = line?;
+            if format == Format::Epd { parse_epd(&line) } else { parse_text(&line) }
+        })),
+    })
+}
+
+pub fn write_position(writer: &mut impl Write, position: &PackedPosition, format: Format) -> io::Result<()> {
+    let fen = position.to_fen();
+    let fields: Vec<&str> = fen.split_whitespace().collect();
+    let reduced_fen = fields[..4].join(" ");
+    match format {
+        Format::Packed => writer.write_all(&position.to_bytes()),
+        Format::Text => writeln!(writer, "{} {}", reduced_fen, position.result_for(WHITE)),
+        Format::Epd => {
+            let score = eval_into_white_viewpoint(position.score as Eval, position.side_to_move());
+            writeln!(writer, "{} ce {}; c9 \"{}\"; hmvc {}; fmvn {};", reduced_fen, score, result_to_pgn(position.result), fields[4], fields[5])
+        },
+        Format::Pgn => {
+            let result = result_to_pgn(position.result);
+            writeln!(writer, "[Result \"{}\"]\n[SetUp \"1\"]\n[FEN \"{}\"]\n\n{{{}}} {}\n", result, fen, position.score, result)
+        },
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+fn parse_text(line: &str) -> io::Result<PackedPosition> {
+    let (fen, result) = line.rsplit_once(' ').ok_or_else(|| invalid_data(format!("no result in \"{line}\"")))?;
+    let result: f64 = result.parse().map_err(|_| invalid_data(format!("invalid result in \"{line}\"")))?;
+    let state = parse_fen(fen, "0", "1")?;
+    Ok(PackedPosition::new(&state, 0, (result * 2.0).round() as u8, 0))
+}
+
+fn parse_epd(line: &str) -> io::Result<PackedPosition> {
+    let fields: Vec<&str> = line.splitn(5, ' ').collect();
+    if fields.len() < 4 {
+        return Err(invalid_data(format!("not an epd: \"{line}\"")));
+    }
+    let (mut score, mut result, mut clock, mut fullmove) = (0, None, "0", "1");
+    for operation in fields.get(4).unwrap_or(&"").split(';').map(str::trim) {
+        let (opcode, operand) = operation.split_once(' ').unwrap_or((operation, ""));
+        match opcode {
+            "ce" => score = operand.parse().map_err(|_| invalid_data(format!("invalid score in \"{line}\"")))?,
+            "c9" => result = pgn_to_result(operand.trim_matches('"')),
+            "hmvc" => clock = operand,
+            "fmvn" => fullmove = operand,
+            _ if opcode.starts_with('[') => result = opcode.trim_matches(['[', ']']).parse::<f64>().ok().map(|result| (result * 2.0).round() as u8),
+            _ => (),
+        }
+    }
+    let result = result.ok_or_else(|| invalid_data(format!("no result in \"{line}\"")))?;
+    let state = parse_fen(&fields[..4].join(" "), clock, fullmove)?;
+    let ply = fullmove.parse::<u16>().unwrap_or(1).saturating_sub(1) * 2 + state.side_to_move() as u16;
+    Ok(PackedPosition::new(&state, eval_into_white_viewpoint(score, state.side_to_move()), result, ply))
+}
+
+/// A fen of four fields with the clocks, checked before `GameState::new_from_fen` panics on it.
+fn parse_fen(fen: &str, clock: &str, fullmove: &str) -> io::Result<GameState> {
+    let fields: Vec<&str> = fen.split_whitespace().collect();
+    let valid_pieces = fields.first().is_some_and(|pieces| pieces.chars().all(|character| "/12345678prnbqkPRNBQK".contains(character)));
+    if fields.len() != 4 || !valid_pieces || clock.parse::<u32>().is_err() {
+        return Err(invalid_data(format!("invalid fen \"{fen}\"")));
+    }
+    Ok(GameState::new_from_fen(&format!("{fen} {clock} {fullmove}")))
+}
+
+fn result_to_pgn(result: u8) -> &'static str {
+    match result {
+        WHITE_WIN => "1-0",
+        BLACK_WIN => "0-1",
+        _ => "1/2-1/2",
+    }
+}
+
+fn pgn_to_result(result: &str) -> Option<u8> {
+    match result {
+        "1-0" => Some(WHITE_WIN),
+        "0-1" => Some(BLACK_WIN),
+        "1/2-1/2" => Some(DRAW),
+        _ => None,
+    }
+}
+
+/// Splits a PGN file into games, each a new set of tags after some movetext.
+struct PgnGames<B: BufRead> {
+    lines: Lines<B>,
+    next_tags: Vec<String>,
+}
+
+impl<B: BufRead> Iterator for PgnGames<B> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut game = std::mem::take(&mut self.next_tags);
+        let mut has_moves = false;
+        for line in self.lines.by_ref() {
+            let line = match line {
+                Ok(line) => line,
+                Err(error) => return Some(Err(error)),
+            };
+            let is_tag = line.trim_start().starts_with('[');
+            if is_tag && has_moves {
+                self.next_tags.push(line);
+                return Some(Ok(game.join("\n")));
+            }
+            has_moves |= !is_tag && !line.trim().is_empty();
+            game.push(line);
+        }
+        (!game.is_empty()).then(|| Ok(game.join("\n")))
+    }
+}
+
+/// Positions before every move of a game with a result, games without one give none.
+fn parse_pgn_game(game: &str) -> io::Result<Vec<PackedPosition>> {
+    let mut tags = vec![];
+    let mut movetext = String::new();
+    for line in game.lines() {
+        let line = line.trim();
+        if let Some(tag) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            if let Some((name, value)) = tag.split_once(' ') {
+                tags.push((name.to_string(), value.trim_matches('"').to_string()));
+            }
+        } else if !line.starts_with('%') {
+            movetext.push_str(line.split(';').next().unwrap_or(""));
+            movetext.push(' ');
+        }
+    }
+    let tag = |name: &str| tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str());
+    let Some(result) = tag("Result").and_then(pgn_to_result) else {
+        return Ok(vec![]);
+    };
+    let (mut state, mut ply) = match tag("FEN") {
+        Some(fen) => {
+            let fields: Vec<&str> = fen.split_whitespace().collect();
+            let state = parse_fen(&fields[..4.min(fields.len())].join(" "), fields.get(4).unwrap_or(&"0"), fields.get(5).unwrap_or(&"1"))?;
+            let fullmove = fields.get(5).and_then(|fullmove| fullmove.parse::<u16>().ok()).unwrap_or(1);
+            let ply = fullmove.saturating_sub(1) * 2 + state.side_to_move() as u16;
+            (state, ply)
+        },
+        None => (GameState::new_starting_pos(), 0),
+    };
+
+    let mut positions = vec![];
+    for token in strip_comments(&movetext).split_whitespace() {
+        if pgn_to_result(token).is_some() || token == "*" {
+            break;
+        }
+        // Move numbers, possibly glued to the move as in "1.e4".
+        let san = token.trim_start_matches(|character: char| character.is_ascii_digit() || character == '.');
+        if san.is_empty() || san.starts_with('$') {
+            continue;
+        }
+        let r#move = parse_san(&mut state, san).ok_or_else(|| invalid_data(format!("illegal move \"{san}\"")))?;
+        positions.push(PackedPosition::new(&state, 0, result, ply));
+        state.apply_legal_move(r#move);
+        ply += 1;
+    }
+    Ok(positions)
+}
+
+/// Drops comments and variations, which may nest.
+fn strip_comments(movetext: &str) -> String {
+    let mut stripped = String::new();
+    let mut in_comment = false;
+    let mut variation_depth = 0;
+    for character in movetext.chars() {
+        match character {
+            '{' => in_comment = true,
+            '}' => in_comment = false,
+            '(' if !in_comment => variation_depth += 1,
+            ')' if !in_comment => variation_depth -= 1,
+            _ if !in_comment && variation_depth == 0 => stripped.push(character),
+            _ => (),
+        }
+    }
+    stripped
+}
+
+fn legal_moves(state: &mut GameState) -> Vec<Move> {
+    state.generate_pseudo_legal_moves().into_iter().filter(|&r#move| {
+        let legal = state.apply_pseudo_legal_move(r#move);
+        if legal {
+            state.undo_move();
+        }
+        legal
+    }).collect()
+}
+
+/// The legal move written as `san`, check marks, annotations and the promotion '=' are optional.
+pub fn parse_san(state: &mut GameState, san: &str) -> Option<Move> {
+    let normalise = |san: &str| san.replace('0', "O").chars().filter(|character| !"+#!?=".contains(*character)).collect::<String>();
+    let wanted = normalise(san);
+    let moves = legal_moves(state);
+    moves.iter().copied().find(|&r#move| normalise(&to_san(r#move, &moves)) == wanted)
+}
+
+/// Standard algebraic notation of a legal move without check marks, `legal_moves` are the moves of its position.
+pub fn to_san(r#move: Move, legal_moves: &[Move]) -> String {
+    match r#move.is_castle_and_where() {
+        Some(CastlingSide::KingSide) => return "O-O".to_string(),
+        Some(CastlingSide::QueenSide) => return "O-O-O".to_string(),
+        _ => (),
+    }
+    let piece = r#move.moving_piece();
+    let from = Move::square_to_algebraic(r#move.from());
+    let mut san = String::new();
+    if piece == PAWN {
+        if r#move.is_capture() {
+            san.push_str(&from[..1]);
+        }
+    } else {
+        san.push(piece_to_char(WHITE, piece));
+        let rivals: Vec<Move> = legal_moves.iter().copied()
+            .filter(|rival| rival.moving_piece() == piece && rival.to() == r#move.to() && rival.from() != r#move.from())
+            .collect();
+        if !rivals.is_empty() {
+            if rivals.iter().all(|rival| rival.from() % 8 != r#move.from() % 8) {
+                san.push_str(&from[..1]);
+            } else if rivals.iter().all(|rival| rival.from() / 8 != r#move.from() / 8) {
+                san.push_str(&from[1..]);
+            } else {
+                san.push_str(&from);
+            }
+        }
+    }
+    if r#move.is_capture() {
+        san.push('x');
+    }
+    san.push_str(&Move::square_to_algebraic(r#move.to()));
+    if r#move.is_promotion() {
+        san.push('=');
+        san.push(piece_to_char(WHITE, r#move.promoted_piece()));
+    }
+    san
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gamestate::GameState;
+    use crate::packed::{PackedPosition, WHITE_WIN};
+
+    use super::{legal_moves, parse_epd, parse_pgn_game, parse_san, parse_text, to_san, write_position, Format};
+
+    #[test]
+    fn test_san_round_trip() {
+        let mut state = GameState::new_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let moves = legal_moves(&mut state);
+        for &r#move in &moves {
+            assert!(parse_san(&mut state, &to_san(r#move, &moves)) == Some(r#move));
+        }
+        assert_eq!(to_san(parse_san(&mut state, "Nxf7").unwrap(), &moves), "Nxf7");
+        assert_eq!(to_san(parse_san(&mut state, "O-O-O").unwrap(), &moves), "O-O-O");
+        // Both rooks can reach d1, the knights on the same file both d4.
+        let mut state = GameState::new_from_fen("4k3/8/8/1N6/8/1N6/5K2/R6R w - - 0 1");
+        let moves = legal_moves(&mut state);
+        assert_eq!(to_san(parse_san(&mut state, "Rad1").unwrap(), &moves), "Rad1");
+        assert_eq!(to_san(parse_san(&mut state, "N5d4").unwrap(), &moves), "N5d4");
+        assert!(parse_san(&mut state, "Rd1").is_none());
+        assert!(parse_san(&mut state, "Nd4").is_none());
+    }
+
+    #[test]
+    fn test_pgn_game() {
+        let game = "[Event \"?\"]\n[Result \"1-0\"]\n\n1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 {the Morphy defence} (3... Nf6 4. O-O)\n4. Ba4 Nf6 5. O-O $1 1-0";
+        let positions = parse_pgn_game(game).unwrap();
+        assert_eq!(positions.len(), 9);
+        assert!(positions.iter().all(|position| position.result == WHITE_WIN));
+        assert_eq!(positions[8].to_fen(), "r1bqkb1r/1ppp1ppp/p1n2n2/4p3/B3P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 2 5");
+        assert!(parse_pgn_game("[Result \"1-0\"]\n\n1. e4 e4 1-0").is_err());
+    }
+
+    #[test]
+    fn test_text_and_epd_round_trip() {
+        let state = GameState::new_from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR b KQkq - 4 4");
+        let position = PackedPosition::new(&state, -35, WHITE_WIN, 7);
+        let mut epd = vec![];
+        write_position(&mut epd, &position, Format::Epd).unwrap();
+        assert!(parse_epd(String::from_utf8(epd).unwrap().trim()).unwrap() == position);
+
+        let mut text = vec![];
+        write_position(&mut text, &position, Format::Text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert_eq!(text, "r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR b KQkq - 1\n");
+        assert_eq!(parse_text(text.trim()).unwrap().to_fen(), "r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR b KQkq - 0 1");
+        assert!(parse_epd("8/8/8 w - -").is_err());
+    }
+}
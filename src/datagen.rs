@@ -0,0 +1,218 @@
+use std::{
+    cell::SyncUnsafeCell,
+    collections::HashSet,
+    env,
+    fs,
+    sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{
+    book::OPENING_BOOK,
+    gamestate::{GameState, WHITE},
+    lockless::LockLessTransTable,
+    packed::{PackedPosition, PackedReader, PackedWriter, BLACK_WIN, DRAW, WHITE_WIN},
+    r#move::Move,
+    smpsearch::{search, Eval, SearchLimits, SearchProtocol, ISMATE, MAX_DEPTH},
+    texel::eval_into_white_viewpoint,
+    timeman::TimeManager,
+};
+
+/// Games starting with a bigger advantage than this after the random opening are thrown away.
+const MAX_OPENING_SCORE: Eval = 1000;
+/// A side scoring this much for `WIN_ADJUDICATION_PLIES` plies in a row has won.
+const WIN_ADJUDICATION_SCORE: Eval = 2000;
+const WIN_ADJUDICATION_PLIES: u32 = 6;
+/// From `DRAW_ADJUDICATION_MIN_PLY` on, scores this close to zero for `DRAW_ADJUDICATION_PLIES` plies are a draw.
+const DRAW_ADJUDICATION_SCORE: Eval = 10;
+const DRAW_ADJUDICATION_PLIES: u32 = 12;
+const DRAW_ADJUDICATION_MIN_PLY: u16 = 80;
+
+#[derive(Parser, Debug, Clone)]
+struct DatagenArgs {
+    /// Packed positions are appended here, positions already in the file count towards `positions`.
+    #[arg(long, default_value = "resources/data.bin")]
+    output: String,
+    /// Total number of positions the file should hold.
+    #[arg(long, default_value_t = 1_000_000)]
+    positions: usize,
+    #[arg(long, default_value_t = 1)]
+    threads: usize,
+    /// Nodes searched for every move.
+    #[arg(long, default_value_t = 5000)]
+    nodes: u64,
+    #[arg(long, default_value_t = MAX_DEPTH as u8)]
+    depth: u8,
+    /// Random moves played at the start of every game, after the book position if there is one.
+    #[arg(long, default_value_t = 8)]
+    random_plies: u16,
+    /// EPD file of opening positions, one is picked at random for every game.
+    #[arg(long)]
+    book: Option<String>,
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+/// `datagen [--output <file>] [--positions <n>] [--threads <n>] ...`: plays self-play games and stores their quiet positions.
+pub fn datagen() {
+    // Skips the program name, clap takes "datagen" for it.
+    let args = DatagenArgs::parse_from(env::args().skip(1));
+    let book: Arc<Vec<String>> = Arc::new(match &args.book {
+        Some(path) => fs::read_to_string(path).expect("Couldn't read the book.").lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.split_whitespace().take(4).collect::<Vec<&str>>().join(" "))
+            .collect(),
+        None => vec![],
+    });
+
+    // Resumes an existing file, whose positions are not written a second time.
+    let mut seen: HashSet<u64> = HashSet::new();
+    let mut written = 0;
+    if let Ok(reader) = PackedReader::open(&args.output) {
+        for position in reader {
+            seen.insert(position.expect("Couldn't read the output file.").to_state().zobrist.0);
+            written += 1;
+        }
+    }
+    println!("Resuming with {} positions in {}", written, args.output);
+    let mut writer = PackedWriter::append(&args.output).expect("Couldn't open the output file.");
+
+    let stop = Arc::new(AtomicBool::new(written >= args.positions));
+    let (sender, receiver) = mpsc::channel::<Vec<(u64, PackedPosition)>>();
+    let seed = args.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    let workers: Vec<_> = (0..args.threads).map(|thread| {
+        let (args, book, stop, sender) = (args.clone(), Arc::clone(&book), Arc::clone(&stop), sender.clone());
+        thread::spawn(move || {
+            let mut rng = StdRng::seed_from_u64(seed.wrapping_add(thread as u64));
+            let trans_table = Arc::new(SyncUnsafeCell::new(LockLessTransTable::new()));
+            while !stop.load(Ordering::Relaxed) {
+                let positions = play_game(&args, &book, &mut rng, &trans_table, &stop);
+                if sender.send(positions).is_err() {
+                    break;
+                }
+            }
+        })
+    }).collect();
+    drop(sender);
+
+    let start = Instant::now();
+    let mut games = 0;
+    for positions in receiver {
+        if written >= args.positions || positions.is_empty() {
+            continue;
+        }
+        games += 1;
+        for (zobrist, position) in positions {
+            if written < args.positions && seen.insert(zobrist) {
+                writer.write(&position).expect("Couldn't write the output file.");
+                written += 1;
+            }
+        }
+        println!("Game {}: {}/{} positions, {:.0} positions/s", games, written, args.positions, written as f64 / start.elapsed().as_secs_f64());
+        if written >= args.positions {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+    writer.flush().expect("Couldn't write the output file.");
+    for worker in workers {
+        worker.join().unwrap();
+    }
+}
+
+/// Plays one game and returns the recorded positions with the result filled in, none if the opening was lopsided.
+fn play_game(args: &DatagenArgs, book: &[String], rng: &mut StdRng, trans_table: &Arc<SyncUnsafeCell<LockLessTransTable>>, stop: &AtomicBool) -> Vec<(u64, PackedPosition)> {
+    let Some(mut state) = random_opening(args.random_plies, book, rng) else {
+        return vec![];
+    };
+    unsafe { (*trans_table.get()).clear(); }
+    let mut ply = args.random_plies;
+    let mut positions = vec![];
+    let mut win_plies = 0;
+    let mut draw_plies = 0;
+    let result = loop {
+        if state.is_game_over() {
+            break game_result(&mut state);
+        }
+        if stop.load(Ordering::Relaxed) {
+            return vec![];
+        }
+        let limits = SearchLimits { max_nodes: args.nodes, ..SearchLimits::new(TimeManager::fixed(Duration::from_secs(3600)), args.depth) };
+        let (best_move, score) = search::<{ SearchProtocol::Texel }>(1, limits, state.clone(), Arc::new(SyncUnsafeCell::new(false)), Arc::clone(trans_table));
+        if ply == args.random_plies && score.abs() > MAX_OPENING_SCORE {
+            return vec![];
+        }
+
+        win_plies = if score.abs() >= WIN_ADJUDICATION_SCORE { win_plies + 1 } else { 0 };
+        if win_plies >= WIN_ADJUDICATION_PLIES {
+            break if eval_into_white_viewpoint(score, state.side_to_move()) > 0 { WHITE_WIN } else { BLACK_WIN };
+        }
+        draw_plies = if ply >= DRAW_ADJUDICATION_MIN_PLY && score.abs() <= DRAW_ADJUDICATION_SCORE { draw_plies + 1 } else { 0 };
+        if draw_plies >= DRAW_ADJUDICATION_PLIES {
+            break DRAW;
+        }
+
+        // Only quiet positions, whose score the static eval can be fitted to.
+        let in_book = OPENING_BOOK.contains_key(&state.to_reduced_book_fen());
+        if !in_book && !state.is_in_check() && !best_move.is_capture() && !best_move.is_promotion() && score.abs() < ISMATE {
+            let white_score = eval_into_white_viewpoint(score, state.side_to_move());
+            positions.push((state.zobrist.0, PackedPosition::new(&state, white_score, DRAW, ply)));
+        }
+        state.apply_legal_move(best_move);
+        ply += 1;
+    };
+    for (_, position) in &mut positions {
+        position.result = result;
+    }
+    positions
+}
+
+/// A book position or the start position followed by `plies` random moves, `None` if the game ended on the way.
+fn random_opening(plies: u16, book: &[String], rng: &mut StdRng) -> Option<GameState> {
+    let mut state = match book.choose(rng) {
+        Some(position) => GameState::new_from_fen(&format!("{position} 0 1")),
+        None => GameState::new_starting_pos(),
+    };
+    for _ in 0..plies {
+        let legal_moves: Vec<Move> = state.generate_pseudo_legal_moves().into_iter().filter(|&r#move| {
+            let legal = state.apply_pseudo_legal_move(r#move);
+            if legal {
+                state.undo_move();
+            }
+            legal
+        }).collect();
+        state.apply_legal_move(*legal_moves.choose(rng)?);
+    }
+    Some(state)
+}
+
+/// Result of a finished game: checkmate loses, every other ending, a check in a drawn ending included, is a draw.
+fn game_result(state: &mut GameState) -> u8 {
+    let checkmated = state.is_in_check() && state.generate_legal_moves().length == 0;
+    if !checkmated {
+        DRAW
+    } else if state.side_to_move() == WHITE {
+        BLACK_WIN
+    } else {
+        WHITE_WIN
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gamestate::GameState;
+    use crate::packed::{DRAW, WHITE_WIN};
+
+    use super::game_result;
+
+    #[test]
+    fn test_game_result() {
+        assert_eq!(game_result(&mut GameState::new_from_fen("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1")), WHITE_WIN);
+        // A check in a drawn ending is no mate.
+        assert_eq!(game_result(&mut GameState::new_from_fen("4k3/8/8/8/8/8/2n5/4R1K1 b - - 0 1")), DRAW);
+        assert_eq!(game_result(&mut GameState::new_from_fen("7k/8/6QK/8/8/8/8/8 b - - 0 1")), DRAW);
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+
+use crate::bitbase::probe_kpk;
+use crate::bitboard::Square;
+use crate::eval::{relative_rank, MATERIAL_VALUE};
+use crate::gamestate::{GameState, Side, BISHOP, BLACK, KING, KNIGHT, PAWN, QUEEN, ROOK, WHITE};
+use crate::smpsearch::Eval;
+
+/// Scale factors are fractions of this, they multiply the endgame half of the eval.
+pub const SCALE_FACTOR_NORMAL: Eval = 64;
+pub const SCALE_FACTOR_DRAW: Eval = 0;
+/// Base score of endings an evaluator knows to be won, far below the mate scores.
+pub const KNOWN_WIN: Eval = 10_000;
+
+/// Piece counts of both sides without the kings, four bits per piece type.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct MaterialSignature(u64);
+
+impl MaterialSignature {
+    pub fn new(state: &GameState) -> Self {
+        let mut signature = 0;
+        for side in [WHITE, BLACK] {
+            for piece in [PAWN, ROOK, KNIGHT, BISHOP, QUEEN] {
+                signature |= (state.piece_boards[side][piece].piece_count() as u64).min(15) << Self::shift(side, piece);
+            }
+        }
+        MaterialSignature(signature)
+    }
+
+    /// Signature of an ending written like "KRPKB", the pieces of the first king belong to `strong`.
+    fn from_code(code: &str, strong: Side) -> Self {
+        let mut signature = 0;
+        let mut side = strong ^ 1;
+        for character in code.chars() {
+            let piece = match character {
+                'K' => {
+                    side ^= 1;
+                    continue;
+                },
+                'P' => PAWN,
+                'R' => ROOK,
+                'N' => KNIGHT,
+                'B' => BISHOP,
+                'Q' => QUEEN,
+                _ => panic!("Invalid piece in endgame code."),
+            };
+            signature += 1 << Self::shift(side, piece);
+        }
+        MaterialSignature(signature)
+    }
+
+    fn shift(side: Side, piece: usize) -> u64 {
+        (side * 20 + piece * 4) as u64
+    }
+}
+
+/// Most pieces, kings included, of an ending in `EVALUATORS`.
+const MAX_REGISTERED_PIECES: u32 = 4;
+
+/// Scores an ending from the point of view of the strong side.
+type Evaluator = fn(&GameState, Side) -> Eval;
+
+lazy_static! {
+    static ref EVALUATORS: HashMap<MaterialSignature, (Evaluator, Side)> = {
+        let endings: [(&str, Evaluator); 5] = [
+            ("KPK", kpk),
+            ("KBNK", kbnk),
+            ("KQKR", kqkr),
+            ("KRKB", krkb),
+            ("KRKN", krkn),
+        ];
+        let mut evaluators = HashMap::new();
+        for (code, evaluator) in endings {
+            for strong in [WHITE, BLACK] {
+                evaluators.insert(MaterialSignature::from_code(code, strong), (evaluator, strong));
+            }
+        }
+        evaluators
+    };
+}
+
+/// Score for the side to move if a specialised evaluator knows the ending.
+pub fn evaluate(state: &GameState) -> Option<Eval> {
+    let (evaluator, strong) = find_evaluator(state)?;
+    let eval = evaluator(state, strong);
+    Some(if state.side_to_move() == strong { eval } else { -eval })
+}
+
+pub fn has_evaluator(state: &GameState) -> bool {
+    find_evaluator(state).is_some()
+}
+
+fn find_evaluator(state: &GameState) -> Option<(Evaluator, Side)> {
+    // Skips hashing the signature in the middlegame, every registered ending is this small.
+    let pieces = state.piece_boards.iter().flatten().map(|board| board.piece_count()).sum::<u32>();
+    if pieces <= MAX_REGISTERED_PIECES {
+        if let Some(&evaluator) = EVALUATORS.get(&MaterialSignature::new(state)) {
+            return Some(evaluator);
+        }
+    }
+    kxk_strong_side(state).map(|strong| (kxk as Evaluator, strong))
+}
+
+/// Fraction of `SCALE_FACTOR_NORMAL` the endgame eval keeps when `strong` is ahead in drawish material.
+pub fn scale_factor(state: &GameState, strong: Side) -> Eval {
+    let weak = strong ^ 1;
+    let strong_material = non_pawn_material(state, strong);
+    let weak_material = non_pawn_material(state, weak);
+
+    // A lone bishop with rook pawns can't drive a king out of the wrong coloured corner.
+    if is_wrong_rook_pawn_bishop(state, strong) {
+        return SCALE_FACTOR_DRAW;
+    }
+
+    // Without pawns a minor piece up is rarely enough to win.
+    if state.piece_boards[strong][PAWN].is_empty() && strong_material - weak_material <= MATERIAL_VALUE[BISHOP] {
+        return if strong_material < MATERIAL_VALUE[ROOK] {
+            SCALE_FACTOR_DRAW
+        } else if weak_material <= MATERIAL_VALUE[BISHOP] {
+            4
+        } else {
+            14
+        };
+    }
+
+    let white_bishops = state.piece_boards[WHITE][BISHOP];
+    let black_bishops = state.piece_boards[BLACK][BISHOP];
+    if white_bishops.piece_count() == 1 && black_bishops.piece_count() == 1
+        && is_dark(white_bishops.next_piece_index()) != is_dark(black_bishops.next_piece_index()) {
+        let only_bishops = [WHITE, BLACK].iter().all(|&side| non_pawn_material(state, side) == MATERIAL_VALUE[BISHOP]);
+        return if only_bishops { 16 } else { 46 };
+    }
+
+    SCALE_FACTOR_NORMAL
+}
+
+fn is_wrong_rook_pawn_bishop(state: &GameState, strong: Side) -> bool {
+    let weak = strong ^ 1;
+    let pawns = state.piece_boards[strong][PAWN];
+    if pawns.is_empty() || state.piece_boards[strong][BISHOP].piece_count() != 1
+        || non_pawn_material(state, strong) != MATERIAL_VALUE[BISHOP] || non_pawn_material(state, weak) != 0 {
+        return false;
+    }
+    let file = pawns.next_piece_index() % 8;
+    if (file != 0 && file != 7) || pawns.into_iter().any(|pawn| pawn % 8 != file) {
+        return false;
+    }
+    let promotion_square = if strong == WHITE { 56 + file } else { file };
+    is_dark(promotion_square) != is_dark(state.piece_boards[strong][BISHOP].next_piece_index())
+        && distance(king_square(state, weak), promotion_square) <= 1
+}
+
+/// The side that has mating material against a bare king.
+fn kxk_strong_side(state: &GameState) -> Option<Side> {
+    [WHITE, BLACK].into_iter().find(|&strong| {
+        let weak = strong ^ 1;
+        let boards = &state.piece_boards[strong];
+        let bare_king = (0..KING).all(|piece| state.piece_boards[weak][piece].is_empty());
+        let bishops_on_both_colours = boards[BISHOP].into_iter().any(is_dark) && boards[BISHOP].into_iter().any(|square| !is_dark(square));
+        bare_king && (boards[QUEEN].is_filled() || boards[ROOK].is_filled() || bishops_on_both_colours
+            || (boards[BISHOP].is_filled() && boards[KNIGHT].is_filled()))
+    })
+}
+
+/// Mating material against a bare king: drive it to the edge with our king close by.
+fn kxk(state: &GameState, strong: Side) -> Eval {
+    let weak_king = king_square(state, strong ^ 1);
+    KNOWN_WIN + state.material_eg[strong] + push_to_edge(weak_king) + push_close(king_square(state, strong), weak_king)
+}
+
+/// Only the corner of the bishop's colour is a mate, so the king is driven there.
+fn kbnk(state: &GameState, strong: Side) -> Eval {
+    let weak_king = king_square(state, strong ^ 1);
+    let bishop = state.piece_boards[strong][BISHOP].next_piece_index();
+    let corner_distance = state.manhatten_distance_corner_bishop(bishop as i64, weak_king as i64);
+    KNOWN_WIN + push_close(king_square(state, strong), weak_king) + 20 * (7 - corner_distance)
+}
+
+fn kqkr(state: &GameState, strong: Side) -> Eval {
+    let weak_king = king_square(state, strong ^ 1);
+    MATERIAL_VALUE[QUEEN] - MATERIAL_VALUE[ROOK] + push_to_edge(weak_king) + push_close(king_square(state, strong), weak_king)
+}
+
+/// Usually a draw, pushing the king to the edge gives the best practical chances.
+fn krkb(state: &GameState, strong: Side) -> Eval {
+    push_to_edge(king_square(state, strong ^ 1))
+}
+
+/// Usually a draw, unless the knight gets separated from its king.
+fn krkn(state: &GameState, strong: Side) -> Eval {
+    let weak_king = king_square(state, strong ^ 1);
+    let knight = state.piece_boards[strong ^ 1][KNIGHT].next_piece_index();
+    push_to_edge(weak_king) + 20 * distance(weak_king, knight) as Eval
+}
+
+/// Exact from the bitbase: a known win pushing the pawn on, or a dead draw.
+fn kpk(state: &GameState, strong: Side) -> Eval {
+    let pawn = state.piece_boards[strong][PAWN].next_piece_index();
+    if !probe_kpk(king_square(state, strong), pawn, king_square(state, strong ^ 1), state.side_to_move(), strong) {
+        return 0;
+    }
+    KNOWN_WIN + MATERIAL_VALUE[PAWN] + 10 * relative_rank(pawn, strong) as Eval
+}
+
+fn non_pawn_material(state: &GameState, side: Side) -> Eval {
+    [ROOK, KNIGHT, BISHOP, QUEEN].iter()
+        .map(|&piece| state.piece_boards[side][piece].piece_count() as Eval * MATERIAL_VALUE[piece])
+        .sum()
+}
+
+fn king_square(state: &GameState, side: Side) -> Square {
+    state.piece_boards[side][KING].next_piece_index()
+}
+
+fn is_dark(square: Square) -> bool {
+    (square / 8 + square % 8) % 2 == 0
+}
+
+/// King moves between two squares.
+pub fn distance(a: Square, b: Square) -> usize {
+    (a % 8).abs_diff(b % 8).max((a / 8).abs_diff(b / 8))
+}
+
+/// Grows towards the edges and corners of the board.
+fn push_to_edge(square: Square) -> Eval {
+    let file = (square % 8).min(7 - square % 8);
+    let rank = (square / 8).min(7 - square / 8);
+    20 * (6 - (file + rank) as Eval)
+}
+
+/// Grows as the two kings come closer.
+fn push_close(a: Square, b: Square) -> Eval {
+    20 * (7 - distance(a, b) as Eval)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gamestate::{GameState, BLACK, WHITE};
+
+    use super::{evaluate, scale_factor, MaterialSignature, KNOWN_WIN, SCALE_FACTOR_DRAW, SCALE_FACTOR_NORMAL};
+
+    #[test]
+    fn test_material_signature() {
+        let state = GameState::new_from_fen("8/8/4k3/8/8/3K4/8/4R3 b - - 0 1");
+        assert_eq!(MaterialSignature::new(&state), MaterialSignature::from_code("KRK", WHITE));
+        let state = GameState::new_from_fen("8/8/4k3/8/4b3/3K4/8/4r3 w - - 0 1");
+        assert_eq!(MaterialSignature::new(&state), MaterialSignature::from_code("KRBK", BLACK));
+    }
+
+    #[test]
+    fn test_known_wins() {
+        let kqk = GameState::new_from_fen("8/8/4k3/8/8/3K4/8/4Q3 w - - 0 1");
+        assert!(evaluate(&kqk).unwrap() > KNOWN_WIN);
+        let kqk = GameState::new_from_fen("8/8/4k3/8/8/3K4/8/4Q3 b - - 0 1");
+        assert!(evaluate(&kqk).unwrap() < -KNOWN_WIN);
+        // The king on the edge scores higher than in the centre.
+        let edge = GameState::new_from_fen("4k3/8/4K3/8/8/8/8/R7 w - - 0 1");
+        let centre = GameState::new_from_fen("8/8/8/4k3/8/3K4/8/R7 w - - 0 1");
+        assert!(evaluate(&edge).unwrap() > evaluate(&centre).unwrap());
+        // King and pawn against king comes from the bitbase.
+        let kpk = GameState::new_from_fen("8/4k3/8/4K3/4P3/8/8/8 b - - 0 1");
+        assert!(evaluate(&kpk).unwrap() < -KNOWN_WIN);
+        let kpk = GameState::new_from_fen("8/4k3/8/4K3/4P3/8/8/8 w - - 0 1");
+        assert_eq!(evaluate(&kpk).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_scale_factors() {
+        let opposite_bishops = GameState::new_from_fen("8/4bk2/8/8/3P4/2KB4/4P3/8 w - - 0 1");
+        assert!(scale_factor(&opposite_bishops, WHITE) < SCALE_FACTOR_NORMAL);
+        let same_bishops = GameState::new_from_fen("8/5k2/8/5b2/3P4/2KB4/4P3/8 w - - 0 1");
+        assert_eq!(scale_factor(&same_bishops, WHITE), SCALE_FACTOR_NORMAL);
+        let wrong_bishop = GameState::new_from_fen("7k/8/8/7P/8/8/4B3/6K1 w - - 0 1");
+        assert_eq!(scale_factor(&wrong_bishop, WHITE), SCALE_FACTOR_DRAW);
+        let right_bishop = GameState::new_from_fen("7k/8/8/7P/8/8/3B4/6K1 w - - 0 1");
+        assert_eq!(scale_factor(&right_bishop, WHITE), SCALE_FACTOR_NORMAL);
+    }
+}
@@ -1,11 +1,14 @@
-use std::cell::SyncUnsafeCell;
 use std::cmp::{min, max};
+use std::slice;
 
 use crate::bitboard::{Bitboard, Square, NUM_OF_SQUARES};
-use crate::gamestate::{GameState, NUM_OF_PIECES, NUM_OF_PLAYERS, Side, KING, PAWN, ROOK, QUEEN, WHITE, BLACK, BISHOP, KNIGHT};
-use crate::movegen::{KING_MOVES, rook_move_bitboard, bishop_move_bitboard, KNIGHT_MOVES, queen_move_bitboard, FILE_BITMASK, RANK_BITMASK, knight_move_bitboard};
-use crate::smpsearch::{Eval, AB_BOUND};
-use clap::Parser;
+use crate::gamestate::{normalize_psqt_square, GameState, NUM_OF_PIECES, NUM_OF_PLAYERS, Side, KING, PAWN, ROOK, QUEEN, WHITE, BLACK, BISHOP, KNIGHT};
+use crate::movegen::{KING_MOVES, rook_move_bitboard, bishop_move_bitboard, queen_move_bitboard, FILE_BITMASK, RANK_BITMASK, knight_move_bitboard, RAY_FROM_TO};
+use crate::r#move::Move;
+use crate::smpsearch::{Eval, NULLMOVE};
+use crate::endgame::{self, SCALE_FACTOR_NORMAL};
+use crate::pawnhash::{probe_pawn_hash, PawnEntry};
+use crate::zobrist::{cuckoo_move, ZobristHash};
 
 const PAWN_VALUE: Eval = 100;
 const ROOK_VALUE: Eval = 500;
@@ -60,50 +63,189 @@ lazy_static! {
     };
 }
 
+/// The evaluation terms a trace breaks `static_eval` into.
+#[derive(Clone, Copy)]
+pub enum Term {
+    Material,
+    Psqt,
+    KnightMobility,
+    BishopMobility,
+    RookMobility,
+    QueenMobility,
+    PawnStructure,
+    PassedPawns,
+    KingSafety,
+    BishopPair,
+    OpenFiles,
+    SeventhRank,
+    Outposts,
+    Threats,
+    Space,
+}
+
+const NUM_OF_TERMS: usize = 15;
+
+const TERM_NAMES: [&str; NUM_OF_TERMS] = [
+    "Material", "PSQT", "Knight mobility", "Bishop mobility", "Rook mobility", "Queen mobility", "Pawn structure",
+    "Passed pawns", "King safety", "Bishop pair", "Open files", "Seventh rank", "Outposts", "Threats", "Space",
+];
+
+/// Receives every term `static_eval_traced` adds up, per side.
+pub trait Tracer {
+    /// Whether anything is recorded, tracing bypasses the caches that would hide terms.
+    const ENABLED: bool;
+
+    fn add(&mut self, term: Term, side: Side, mg: Eval, eg: Eval);
+
+    fn note(&mut self, _note: &'static str) {}
+
+    /// The scale factor of the endgame half, out of `SCALE_FACTOR_NORMAL`.
+    fn scale_eg(&mut self, _scale_factor: Eval) {}
+}
+
+/// The tracer of the search, which compiles down to the untraced evaluation.
+pub struct NoTrace;
+
+impl Tracer for NoTrace {
+    const ENABLED: bool = false;
+
+    #[inline(always)]
+    fn add(&mut self, _term: Term, _side: Side, _mg: Eval, _eg: Eval) {}
+}
+
+/// Sums of every term per side for the `trace` command.
+#[derive(Default)]
+pub struct EvalTrace {
+    terms: [[(Eval, Eval); NUM_OF_PLAYERS]; NUM_OF_TERMS],
+    notes: Vec<&'static str>,
+    scale_factor: Option<Eval>,
+}
+
+impl Tracer for EvalTrace {
+    const ENABLED: bool = true;
+
+    fn add(&mut self, term: Term, side: Side, mg: Eval, eg: Eval) {
+        let (term_mg, term_eg) = &mut self.terms[term as usize][side];
+        *term_mg += mg;
+        *term_eg += eg;
+    }
+
+    fn note(&mut self, note: &'static str) {
+        self.notes.push(note);
+    }
+
+    fn scale_eg(&mut self, scale_factor: Eval) {
+        self.scale_factor = Some(scale_factor);
+    }
+}
+
+impl EvalTrace {
+    /// Sum of all terms from white's point of view, blended by `phase`.
+    pub fn total(&self, phase: Eval) -> Eval {
+        let (mg, eg) = self.terms.iter().fold((0, 0), |(mg, eg), [(white_mg, white_eg), (black_mg, black_eg)]| {
+            (mg + white_mg - black_mg, eg + white_eg - black_eg)
+        });
+        let eg = eg * self.scale_factor.unwrap_or(SCALE_FACTOR_NORMAL) / SCALE_FACTOR_NORMAL;
+        (mg * (256 - phase) + eg * phase) / 256
+    }
+
+    /// Prints the terms of both sides and their difference from white's point of view,
+    /// blended by `phase` like `static_eval` does.
+    pub fn print(&self, phase: Eval, eval: Eval) {
+        let blend = |mg: Eval, eg: Eval| (mg * (256 - phase) + eg * phase) / 256;
+        println!("{:>16} | {:>6} {:>6} | {:>6} {:>6} | {:>6} {:>6} {:>6}", "Term", "W mg", "W eg", "B mg", "B eg", "mg", "eg", "total");
+        println!("{}", "-".repeat(73));
+        let mut total = (0, 0);
+        for (name, [(white_mg, white_eg), (black_mg, black_eg)]) in TERM_NAMES.iter().zip(self.terms) {
+            let (mg, eg) = (white_mg - black_mg, white_eg - black_eg);
+            total = (total.0 + mg, total.1 + eg);
+            println!("{:>16} | {:>6} {:>6} | {:>6} {:>6} | {:>6} {:>6} {:>6}", name, white_mg, white_eg, black_mg, black_eg, mg, eg, blend(mg, eg));
+        }
+        println!("{}", "-".repeat(73));
+        println!("{:>16} | {:>13} | {:>13} | {:>6} {:>6} {:>6}", "Total", "", "", total.0, total.1, self.total(phase));
+        println!("Phase: {} / 256 (0 is the opening)", phase);
+        if let Some(scale_factor) = self.scale_factor {
+            println!("Endgame scale factor: {} / {}", scale_factor, SCALE_FACTOR_NORMAL);
+        }
+        for note in &self.notes {
+            println!("Note: {}", note);
+        }
+        println!("Static eval (side to move): {}", eval);
+    }
+}
+
 impl GameState {
     pub fn static_eval(&self) -> Eval {
+        if let Some(network) = self.accumulator.network {
+            // Known endings stay exact, the network evaluates everything else.
+            return endgame::evaluate(self).unwrap_or_else(|| network.evaluate(&self.accumulator, self.side_to_move()));
+        }
+        self.static_eval_traced(&mut NoTrace)
+    }
+
+    /// `static_eval` reporting every term to `tracer`.
+    pub fn static_eval_traced<T: Tracer>(&self, tracer: &mut T) -> Eval {
+        self.evaluate(unsafe { &EVAL_PARAMS }, tracer)
+    }
+
+    /// The classical eval with `params` in place of the engine's own, for tuning.
+    pub fn static_eval_with(&self, params: &EvalParams) -> Eval {
+        self.evaluate(params, &mut NoTrace)
+    }
+
+    fn evaluate<T: Tracer>(&self, params: &EvalParams, tracer: &mut T) -> Eval {
         let our_side = self.side_to_move();
         let enemy_side = our_side ^ 1;
+        if let Some(eval) = endgame::evaluate(self) {
+            tracer.note("specialised endgame evaluator, the terms below are not used");
+            return eval;
+        }
         // Is material draw?
         if self.piece_boards[WHITE][PAWN].is_empty() && self.piece_boards[BLACK][PAWN].is_empty() {
-            if let Some(side) = self.knight_bishop_lonesome_side() {
-                if side == enemy_side {
-                    return 20 - self.bishop_knight_ending_eval(side);
-                } else {
-                    return -20 + self.bishop_knight_ending_eval(side);
-                }
-            }
-
             if self.is_material_draw() {
+                tracer.note("material draw, the terms below are not used");
                 return 0;
             }
         }
 
         // Material Value
-        let mut mg_eval = self.mg_eval(our_side, enemy_side);
-        let mut eg_eval = self.eg_eval(our_side, enemy_side);
+        let material = [self.material_and_psqt(params, WHITE), self.material_and_psqt(params, BLACK)];
+        let mut mg_eval = (material[our_side].0 - material[enemy_side].0) + (material[our_side].2 - material[enemy_side].2);
+        let mut eg_eval = (material[our_side].1 - material[enemy_side].1) + (material[our_side].3 - material[enemy_side].3);
+        for side in [WHITE, BLACK] {
+            tracer.add(Term::Material, side, material[side].0, material[side].1);
+            tracer.add(Term::Psqt, side, material[side].2, material[side].3);
+        }
         let blockers = self.occupancy(our_side) | self.occupancy(enemy_side);
-        let mobility_us = self.mobility(our_side, self.mobility_area(our_side), blockers);
-        let mobilits_enemy = self.mobility(enemy_side, self.mobility_area(enemy_side), blockers);
+        let mobility_us = self.mobility(params, our_side, self.mobility_area(our_side), blockers, tracer);
+        let mobilits_enemy = self.mobility(params, enemy_side, self.mobility_area(enemy_side), blockers, tracer);
         mg_eval += mobility_us.0 - mobilits_enemy.0;
         eg_eval += mobility_us.1 - mobilits_enemy.1;
-        let pawns_us = self.pawns(our_side);
-        let pawns_enemy = self.pawns(enemy_side);
-        mg_eval += pawns_us.0 - pawns_enemy.0;
-        eg_eval += pawns_us.1 - pawns_enemy.1;
-        // mg_eval += self.king_safety_mg(our_side) - self.king_safety_mg(enemy_side);
+        let pawns = self.pawn_structure(params, tracer);
+        mg_eval += pawns.mg[our_side] - pawns.mg[enemy_side];
+        eg_eval += pawns.eg[our_side] - pawns.eg[enemy_side];
+        eg_eval += self.passed_pawn_kings(params, our_side, pawns.passed[our_side], tracer) - self.passed_pawn_kings(params, enemy_side, pawns.passed[enemy_side], tracer);
+        mg_eval += self.king_safety_mg(params, our_side, blockers, tracer) - self.king_safety_mg(params, enemy_side, blockers, tracer);
+        let pieces_us = self.pieces(params, our_side, tracer);
+        let pieces_enemy = self.pieces(params, enemy_side, tracer);
+        mg_eval += pieces_us.0 - pieces_enemy.0;
+        eg_eval += pieces_us.1 - pieces_enemy.1;
+        let attacks = [self.attacks(WHITE, blockers), self.attacks(BLACK, blockers)];
+        let threats_us = self.threats(params, our_side, &attacks, blockers, tracer);
+        let threats_enemy = self.threats(params, enemy_side, &attacks, blockers, tracer);
+        mg_eval += threats_us.0 - threats_enemy.0;
+        eg_eval += threats_us.1 - threats_enemy.1;
+        mg_eval += self.space_mg(params, our_side, tracer) - self.space_mg(params, enemy_side, tracer);
+        // Drawish material only keeps part of the endgame advantage.
+        let strong_side = if eg_eval > 0 { our_side } else { enemy_side };
+        let scale_factor = endgame::scale_factor(self, strong_side);
+        tracer.scale_eg(scale_factor);
+        eg_eval = eg_eval * scale_factor / SCALE_FACTOR_NORMAL;
         let phase = self.phase();
 
         ((mg_eval * (256 - phase)) + (eg_eval * phase)) / 256
     }
 
-    pub fn bishop_knight_ending_eval(&self, lonesome_side: Side) -> Eval {
-        let king_square = self.piece_boards[lonesome_side][KING].next_piece_index() as i64;
-        let bishop_square = self.piece_boards[lonesome_side ^ 1][BISHOP].next_piece_index() as i64;
-        let manhatten_distance = self.manhatten_distance_corner_bishop(bishop_square, king_square);
-        manhatten_distance * 100
-    }
-
     pub fn manhatten_distance_corner_bishop(&self, bishop_square: i64, king_square: i64) -> Eval {
         let b: i64 = -1879048192 * bishop_square >> 31;
         let k: i64 = (king_square>>3) + ((king_square^b) & 7);
@@ -111,20 +253,44 @@ impl GameState {
         k as Eval
     }
 
-    fn pawns(&self, our_side: Side) -> (Eval, Eval) {
+    /// Whether the already applied `move` pushed a passed pawn to its seventh rank.
+    pub fn is_passed_pawn_push(&self, r#move: Move, side: Side) -> bool {
+        let seventh_rank = if side == WHITE { 6 } else { 1 };
+        r#move.moving_piece() == PAWN && !r#move.is_promotion() && r#move.to() / 8 == seventh_rank
+            && (PASSED_MASK[side][r#move.to()] & self.piece_boards[side ^ 1][PAWN]).is_empty()
+    }
+
+    /// Pawn scores of both sides, cached in the pawn hash table since they only depend on the pawns.
+    /// A trace bypasses the table, cached entries don't know their terms, and so do params other than the engine's.
+    pub fn pawn_structure<T: Tracer>(&self, params: &EvalParams, tracer: &mut T) -> PawnEntry {
+        if T::ENABLED || !is_engine_params(params) {
+            self.evaluate_pawns(params, tracer)
+        } else {
+            probe_pawn_hash(self.pawn_zobrist, || self.evaluate_pawns(params, &mut NoTrace))
+        }
+    }
+
+    fn evaluate_pawns<T: Tracer>(&self, params: &EvalParams, tracer: &mut T) -> PawnEntry {
+        let (white_mg, white_eg, white_passed) = self.pawns(params, WHITE, tracer);
+        let (black_mg, black_eg, black_passed) = self.pawns(params, BLACK, tracer);
+        PawnEntry::new(self.pawn_zobrist, [white_mg, black_mg], [white_eg, black_eg], [white_passed, black_passed])
+    }
+
+    fn pawns<T: Tracer>(&self, params: &EvalParams, our_side: Side, tracer: &mut T) -> (Eval, Eval, Bitboard) {
         let mut pawns_mg = 0;
         let mut pawns_eg = 0;
+        let mut passed = Bitboard(0);
         for pawn in self.piece_boards[our_side][PAWN] {
             if (PASSED_MASK[our_side][pawn] & self.piece_boards[our_side ^ 1][PAWN]).is_empty() {
+                passed |= Bitboard::square(pawn);
                 let passed_rank = if our_side == WHITE {
                     pawn / 8
                 } else {
                     8 - (pawn / 8)
                 };
-                unsafe {
-                    pawns_mg += EVAL_PARAMS.mg_passed[passed_rank];
-                    pawns_eg += EVAL_PARAMS.eg_passed[passed_rank];
-                }
+                pawns_mg += params.mg_passed[passed_rank];
+                pawns_eg += params.eg_passed[passed_rank];
+                tracer.add(Term::PassedPawns, our_side, params.mg_passed[passed_rank], params.eg_passed[passed_rank]);
             }
             let file = pawn % 8;
             let isolated: bool = (ISOLATED_MASKS[file] & self.piece_boards[our_side][PAWN]).is_empty();
@@ -138,24 +304,48 @@ impl GameState {
                 (pawn_up_one & self.piece_boards[our_side][PAWN]).is_filled()
             };
             if isolated {
-                unsafe {
-                    pawns_mg -= EVAL_PARAMS.mg_isolated_penalty;
-                    pawns_eg -= EVAL_PARAMS.eg_isolated_penalty;
-                }
+                pawns_mg -= params.mg_isolated_penalty;
+                pawns_eg -= params.eg_isolated_penalty;
+                tracer.add(Term::PawnStructure, our_side, -params.mg_isolated_penalty, -params.eg_isolated_penalty);
             }
             if doubled {
-                unsafe {
-                    pawns_mg -= EVAL_PARAMS.mg_doubled_penalty;
-                    pawns_eg -= EVAL_PARAMS.eg_doubled_penalty;
-                }
+                pawns_mg -= params.mg_doubled_penalty;
+                pawns_eg -= params.eg_doubled_penalty;
+                tracer.add(Term::PawnStructure, our_side, -params.mg_doubled_penalty, -params.eg_doubled_penalty);
             }
             let supported_count = self.supported_count(our_side, pawn) as Eval;
-            unsafe {
-                pawns_mg += supported_count * EVAL_PARAMS.mg_supported_bonus;
-                pawns_eg += supported_count * EVAL_PARAMS.eg_supported_bonus;
+            pawns_mg += supported_count * params.mg_supported_bonus;
+            pawns_eg += supported_count * params.eg_supported_bonus;
+            tracer.add(Term::PawnStructure, our_side, supported_count * params.mg_supported_bonus, supported_count * params.eg_supported_bonus);
+        }
+        (pawns_mg, pawns_eg, passed)
+    }
+
+    /// Endgame bonus of the `passed` pawns the enemy king is further from than ours. It depends on the kings,
+    /// so the pawn hash only provides the passed pawns.
+    fn passed_pawn_kings<T: Tracer>(&self, params: &EvalParams, our_side: Side, passed: Bitboard, tracer: &mut T) -> Eval {
+        let our_king = self.piece_boards[our_side][KING].next_piece_index();
+        let enemy_king = self.piece_boards[our_side ^ 1][KING].next_piece_index();
+        let mut passed_eg = 0;
+        for pawn in passed {
+            let rank = relative_rank(pawn, our_side);
+            // The kings have time to walk over to pawns on their first ranks.
+            if rank < 3 {
+                continue;
             }
+            let stop_square = if our_side == WHITE { pawn + 8 } else { pawn - 8 };
+            let own_distance = endgame::distance(our_king, stop_square).min(5) as Eval;
+            let enemy_distance = endgame::distance(enemy_king, stop_square).min(5) as Eval;
+            passed_eg += (rank as Eval - 2) * (enemy_distance * params.eg_passed_enemy_king_distance - own_distance * params.eg_passed_own_king_distance);
         }
-        (pawns_mg, pawns_eg)
+        tracer.add(Term::PassedPawns, our_side, 0, passed_eg);
+        passed_eg
+    }
+
+    /// Pawnless endings without mating material that no specialised evaluator plays on, `static_eval` scores them as 0.
+    pub fn is_drawn_ending(&self) -> bool {
+        self.piece_boards[WHITE][PAWN].is_empty() && self.piece_boards[BLACK][PAWN].is_empty() && self.is_material_draw()
+            && !endgame::has_evaluator(self)
     }
 
     pub fn is_material_draw(&self) -> bool {
@@ -175,47 +365,78 @@ impl GameState {
         false
     }
 
-    pub fn knight_bishop_lonesome_side(&self) -> Option<Side> {
-        if self.material[WHITE] == 0 && self.piece_boards[BLACK][ROOK].piece_count() == 0 && self.piece_boards[BLACK][QUEEN].piece_count() == 0
-        && self.piece_boards[BLACK][BISHOP].piece_count() == 1 && self.piece_boards[BLACK][KNIGHT].piece_count() == 1 {
-            return Some(WHITE);
+    /// Material and piece-square scores of `side` as (material mg, material eg, psqt mg, psqt eg). The engine's params
+    /// are kept up to date incrementally, others are summed up from the board.
+    fn material_and_psqt(&self, params: &EvalParams, side: Side) -> (Eval, Eval, Eval, Eval) {
+        if is_engine_params(params) {
+            return (self.material[side], self.material_eg[side], self.psqt_mg[side], self.psqt_eg[side]);
         }
-        if self.material[BLACK] == 0 && self.piece_boards[WHITE][ROOK].piece_count() == 0 && self.piece_boards[WHITE][QUEEN].piece_count() == 0 
-        && self.piece_boards[WHITE][BISHOP].piece_count() == 1 && self.piece_boards[WHITE][KNIGHT].piece_count() == 1 {
-            return Some(BLACK);
+        let mut scores = (0, 0, 0, 0);
+        for piece in 0..NUM_OF_PIECES {
+            for square in self.piece_boards[side][piece] {
+                let psqt_square = normalize_psqt_square(square, side);
+                scores.0 += params.mg_piece_value[piece];
+                scores.1 += params.eg_piece_value[piece];
+                scores.2 += params.psqt_mg[piece][psqt_square];
+                scores.3 += params.psqt_eg[piece][psqt_square];
+            }
         }
-        None
+        scores
     }
 
-    fn mg_eval(&self, our_side: Side, enemy_side: Side) -> Eval {
-        (self.material_mg(our_side) - self.material_mg(enemy_side))
-        + (self.psqt_mg(our_side) - self.psqt_mg(enemy_side))
-    }
-
-    fn material_mg(&self, our_side: Side) -> Eval {
-        self.material[our_side]
-    }
-
-    fn psqt_mg(&self, our_side: Side) -> Eval {
-        self.psqt_mg[our_side]
-    }
+    /// Attack units of the enemy pieces hitting the king zone, looked up in the safety table once two pieces
+    /// join the attack, plus pawn shelter, pawn storm and open files on the king file and its neighbours.
+    fn king_safety_mg<T: Tracer>(&self, params: &EvalParams, our_side: Side, blockers: Bitboard, tracer: &mut T) -> Eval {
+        let enemy_side = our_side ^ 1;
+        let king_square = self.piece_boards[our_side][KING].next_piece_index();
+        let king_zone = KING_MOVES[king_square] | Bitboard::square(king_square);
 
-    fn king_safety_mg(&self, our_side: Side) -> Eval {
-        let king_square = self.piece_boards[our_side][KING];
-        let king_square_index = king_square.next_piece_index();
-        let king_file = king_square_index % 8;
-        let mut eval = 0;
-        for file_around_king in max(king_file - 1, 0)..min(king_file, 8) {
-            if (FILE_BITMASK[file_around_king] & self.piece_boards[our_side][PAWN]).is_empty() {
-                unsafe {
-                    eval -= EVAL_PARAMS.open_king_file_punish_mg;
+        let mut attackers = 0;
+        let mut attack_units = 0;
+        for piece in [ROOK, KNIGHT, BISHOP, QUEEN] {
+            for square in self.piece_boards[enemy_side][piece] {
+                let attacks = match piece {
+                    ROOK => rook_move_bitboard(square, blockers),
+                    KNIGHT => knight_move_bitboard(square),
+                    BISHOP => bishop_move_bitboard(square, blockers),
+                    _ => queen_move_bitboard(square, blockers),
+                } & king_zone;
+                if attacks.is_filled() {
+                    attackers += 1;
+                    attack_units += params.king_attack_weight[piece] * attacks.piece_count() as Eval;
                 }
             }
         }
+        let mut eval = 0;
+        if attackers >= 2 {
+            eval -= params.king_safety_table[min(attack_units as usize, params.king_safety_table.len() - 1)];
+        }
+
+        let king_file = king_square % 8;
+        let king_rank = relative_rank(king_square, our_side);
+        for file in king_file.saturating_sub(1)..=min(king_file + 1, 7) {
+            let our_pawns = FILE_BITMASK[file] & self.piece_boards[our_side][PAWN];
+            let enemy_pawns = FILE_BITMASK[file] & self.piece_boards[enemy_side][PAWN];
+            // Closest pawn in front of the king by its rank seen from our side, 0 if there is none.
+            let closest_in_front = |pawns: Bitboard| pawns.into_iter()
+                .map(|pawn| relative_rank(pawn, our_side))
+                .filter(|&rank| rank > king_rank)
+                .min()
+                .unwrap_or(0);
+            eval += params.king_shelter[closest_in_front(our_pawns)] - params.king_storm[closest_in_front(enemy_pawns)];
+            if our_pawns.is_empty() {
+                eval -= if enemy_pawns.is_empty() {
+                    params.open_king_file_punish_mg
+                } else {
+                    params.half_open_king_file_punish_mg
+                };
+            }
+        }
+        tracer.add(Term::KingSafety, our_side, eval, 0);
         eval
-    }   
+    }
 
-    fn mobility(&self, our_side: Side, mobility_area: Bitboard, blockers: Bitboard) -> (Eval, Eval) {
+    fn mobility<T: Tracer>(&self, params: &EvalParams, our_side: Side, mobility_area: Bitboard, blockers: Bitboard, tracer: &mut T) -> (Eval, Eval) {
         let mut mg_eval = 0;
         let mut eg_eval = 0;
 
@@ -225,45 +446,158 @@ impl GameState {
             let moves = rook_move_bitboard(piece, blockers);
             let mobile_moves = moves & mobility_area & !defended_by_minors;
             let mobile_move_count = mobile_moves.0.count_ones() as usize;
-            unsafe {
-                mg_eval += EVAL_PARAMS.mg_rook_mobility[mobile_move_count];
-                eg_eval += EVAL_PARAMS.eg_rook_mobility[mobile_move_count];
-            }
+            mg_eval += params.mg_rook_mobility[mobile_move_count];
+            eg_eval += params.eg_rook_mobility[mobile_move_count];
+            tracer.add(Term::RookMobility, our_side, params.mg_rook_mobility[mobile_move_count], params.eg_rook_mobility[mobile_move_count]);
         }
 
         for piece in self.piece_boards[our_side][QUEEN] {
             let moves = queen_move_bitboard(piece, blockers);
             let mobile_moves = moves & mobility_area & !defended_by_minors;
             let mobile_move_count = mobile_moves.0.count_ones() as usize;
-            unsafe {
-                mg_eval += EVAL_PARAMS.mg_queen_mobility[mobile_move_count];
-                eg_eval += EVAL_PARAMS.eg_queen_mobility[mobile_move_count];
-            }
+            mg_eval += params.mg_queen_mobility[mobile_move_count];
+            eg_eval += params.eg_queen_mobility[mobile_move_count];
+            tracer.add(Term::QueenMobility, our_side, params.mg_queen_mobility[mobile_move_count], params.eg_queen_mobility[mobile_move_count]);
         }
 
         for piece in self.piece_boards[our_side][BISHOP] {
             let moves = bishop_move_bitboard(piece, blockers);
             let mobile_moves = moves & mobility_area;
             let mobile_move_count = mobile_moves.0.count_ones() as usize;
-            unsafe {
-                mg_eval += EVAL_PARAMS.mg_bishop_mobility[mobile_move_count];
-                eg_eval += EVAL_PARAMS.eg_bishop_mobility[mobile_move_count];
-            }
+            mg_eval += params.mg_bishop_mobility[mobile_move_count];
+            eg_eval += params.eg_bishop_mobility[mobile_move_count];
+            tracer.add(Term::BishopMobility, our_side, params.mg_bishop_mobility[mobile_move_count], params.eg_bishop_mobility[mobile_move_count]);
         }
 
         for piece in self.piece_boards[our_side][KNIGHT] {
             let moves = knight_move_bitboard(piece);
             let mobile_moves = moves & mobility_area;
             let mobile_move_count = mobile_moves.0.count_ones() as usize;
-            unsafe {
-                mg_eval += EVAL_PARAMS.mg_knight_mobility[mobile_move_count];
-                eg_eval += EVAL_PARAMS.eg_knight_mobility[mobile_move_count];
+            mg_eval += params.mg_knight_mobility[mobile_move_count];
+            eg_eval += params.eg_knight_mobility[mobile_move_count];
+            tracer.add(Term::KnightMobility, our_side, params.mg_knight_mobility[mobile_move_count], params.eg_knight_mobility[mobile_move_count]);
+        }
+
+        (mg_eval, eg_eval)
+    }
+
+    /// Bishop pair, rooks and queens on open files and the seventh rank, and minor pieces on outposts.
+    fn pieces<T: Tracer>(&self, params: &EvalParams, our_side: Side, tracer: &mut T) -> (Eval, Eval) {
+        let enemy_side = our_side ^ 1;
+        let mut mg_eval = 0;
+        let mut eg_eval = 0;
+
+        if self.piece_boards[our_side][BISHOP].piece_count() >= 2 {
+            mg_eval += params.mg_bishop_pair;
+            eg_eval += params.eg_bishop_pair;
+            tracer.add(Term::BishopPair, our_side, params.mg_bishop_pair, params.eg_bishop_pair);
+        }
+
+        let our_pawns = self.piece_boards[our_side][PAWN];
+        let enemy_pawns = self.piece_boards[enemy_side][PAWN];
+        let enemy_king_rank = relative_rank(self.piece_boards[enemy_side][KING].next_piece_index(), our_side);
+        let seventh_rank = RANK_BITMASK[if our_side == WHITE { 6 } else { 1 }];
+        // Only worth something if it cuts off the king or eats pawns.
+        let seventh_rank_matters = enemy_king_rank == 7 || (enemy_pawns & seventh_rank).is_filled();
+        for (piece, mg_semi_open, eg_semi_open, mg_open, eg_open, mg_seventh, eg_seventh) in [
+            (ROOK, params.mg_rook_semi_open_file, params.eg_rook_semi_open_file, params.mg_rook_open_file, params.eg_rook_open_file, params.mg_rook_on_seventh, params.eg_rook_on_seventh),
+            (QUEEN, params.mg_queen_semi_open_file, params.eg_queen_semi_open_file, params.mg_queen_open_file, params.eg_queen_open_file, params.mg_queen_on_seventh, params.eg_queen_on_seventh),
+        ] {
+            for square in self.piece_boards[our_side][piece] {
+                let file = FILE_BITMASK[square % 8];
+                if (file & our_pawns).is_empty() {
+                    if (file & enemy_pawns).is_empty() {
+                        mg_eval += mg_open;
+                        eg_eval += eg_open;
+                        tracer.add(Term::OpenFiles, our_side, mg_open, eg_open);
+                    } else {
+                        mg_eval += mg_semi_open;
+                        eg_eval += eg_semi_open;
+                        tracer.add(Term::OpenFiles, our_side, mg_semi_open, eg_semi_open);
+                    }
+                }
+                if seventh_rank_matters && relative_rank(square, our_side) == 6 {
+                    mg_eval += mg_seventh;
+                    eg_eval += eg_seventh;
+                    tracer.add(Term::SeventhRank, our_side, mg_seventh, eg_seventh);
+                }
+            }
+        }
+
+        // An outpost is defended by our pawns and can never be chased away by an enemy pawn.
+        let pawn_defended = self.pawn_attacks(our_side);
+        for (piece, mg_outpost, eg_outpost) in [
+            (KNIGHT, params.mg_knight_outpost, params.eg_knight_outpost),
+            (BISHOP, params.mg_bishop_outpost, params.eg_bishop_outpost),
+        ] {
+            for square in self.piece_boards[our_side][piece] & pawn_defended {
+                let rank = relative_rank(square, our_side);
+                if (3..=5).contains(&rank)
+                    && (PASSED_MASK[our_side][square] & ISOLATED_MASKS[square % 8] & enemy_pawns).is_empty() {
+                    mg_eval += mg_outpost;
+                    eg_eval += eg_outpost;
+                    tracer.add(Term::Outposts, our_side, mg_outpost, eg_outpost);
+                }
             }
         }
 
         (mg_eval, eg_eval)
     }
 
+    /// Hanging enemy pieces, and enemy pieces attacked by our pawns or by our minors when they are worth more.
+    fn threats<T: Tracer>(&self, params: &EvalParams, our_side: Side, attacks: &[Bitboard; NUM_OF_PLAYERS], blockers: Bitboard, tracer: &mut T) -> (Eval, Eval) {
+        let enemy_side = our_side ^ 1;
+        let enemy_pieces = self.occupancy(enemy_side) & !self.piece_boards[enemy_side][PAWN] & !self.piece_boards[enemy_side][KING];
+        let enemy_majors = self.piece_boards[enemy_side][ROOK] | self.piece_boards[enemy_side][QUEEN];
+
+        let hanging = (enemy_pieces & attacks[our_side] & !attacks[enemy_side]).piece_count() as Eval;
+        let pawn_threats = (enemy_pieces & self.pawn_attacks(our_side)).piece_count() as Eval;
+        let minor_threats = (enemy_majors & self.defended_by_minors(our_side, blockers)).piece_count() as Eval;
+
+        let mg_eval = hanging * params.mg_hanging + pawn_threats * params.mg_pawn_threat + minor_threats * params.mg_minor_threat;
+        let eg_eval = hanging * params.eg_hanging + pawn_threats * params.eg_pawn_threat + minor_threats * params.eg_minor_threat;
+        tracer.add(Term::Threats, our_side, mg_eval, eg_eval);
+        (mg_eval, eg_eval)
+    }
+
+    /// Squares on our relative ranks 2-4 of the four centre files that hold no own pawn and no enemy pawn
+    /// attacks. Only the middlegame cares for space.
+    fn space_mg<T: Tracer>(&self, params: &EvalParams, our_side: Side, tracer: &mut T) -> Eval {
+        let enemy_side = our_side ^ 1;
+        let centre_files = FILE_BITMASK[2] | FILE_BITMASK[3] | FILE_BITMASK[4] | FILE_BITMASK[5];
+        let our_half = if our_side == WHITE {
+            RANK_BITMASK[1] | RANK_BITMASK[2] | RANK_BITMASK[3]
+        } else {
+            RANK_BITMASK[6] | RANK_BITMASK[5] | RANK_BITMASK[4]
+        };
+        let safe = centre_files & our_half & !self.piece_boards[our_side][PAWN] & !self.pawn_attacks(enemy_side);
+        let space = safe.piece_count() as Eval * params.mg_space;
+        tracer.add(Term::Space, our_side, space, 0);
+        space
+    }
+
+    /// Squares attacked by the pawns of `side`.
+    fn pawn_attacks(&self, side: Side) -> Bitboard {
+        let pawns = self.piece_boards[side][PAWN];
+        if side == WHITE {
+            ((pawns & !FILE_BITMASK[0]) << 7) | ((pawns & !FILE_BITMASK[7]) << 9)
+        } else {
+            ((pawns & !FILE_BITMASK[7]) >> 7) | ((pawns & !FILE_BITMASK[0]) >> 9)
+        }
+    }
+
+    /// Every square attacked by a piece of `side`.
+    fn attacks(&self, side: Side, blockers: Bitboard) -> Bitboard {
+        let mut attacks = self.pawn_attacks(side) | self.defended_by_minors(side, blockers);
+        for square in self.piece_boards[side][ROOK] {
+            attacks |= rook_move_bitboard(square, blockers);
+        }
+        for square in self.piece_boards[side][QUEEN] {
+            attacks |= queen_move_bitboard(square, blockers);
+        }
+        attacks | KING_MOVES[self.piece_boards[side][KING].next_piece_index()]
+    }
+
     fn defended_by_minors(&self, our_side: Side, blockers: Bitboard) -> Bitboard {
         let mut defended = Bitboard(0);
         for piece in self.piece_boards[our_side][KNIGHT] {
@@ -277,19 +611,6 @@ impl GameState {
         defended
     } 
 
-    fn eg_eval(&self, our_side: Side, enemy_side: Side) -> Eval {
-        (self.material_eg(our_side) - self.material_eg(enemy_side))
-        + (self.psqt_eg(our_side) - self.psqt_eg(enemy_side))
-    }
-
-    fn material_eg(&self, our_side: Side) -> Eval {
-        self.material_eg[our_side]
-    }
-
-    fn psqt_eg(&self, our_side: Side) -> Eval {
-        self.psqt_eg[our_side]
-    }
-
     fn mobility_area(&self, side: Side) -> Bitboard {
         let mut area = Bitboard::full();
         let enemy_side = side ^ 1;
@@ -316,37 +637,61 @@ impl GameState {
         phase as Eval
     }
 
+    /// Draw by repetition as the search sees it: one earlier occurrence inside the search tree is enough,
+    /// positions from before the root have to occur twice.
     pub fn has_repitition(&self) -> bool {
-        for index in (0..self.history.len()).rev() {
-            if self.history[index].zobrist == self.zobrist {
-                return true;
-            }
-        }
-        false
+        self.is_repetition(self.search_ply as usize)
     }
 
-    pub fn calculate_movetime(&self, wtime: u64, btime: u64, winc: u64, binc: u64) -> u64 {
-        let time_left_move = if self.side_to_move() == WHITE {
-            self.calculate_movetime_simple_side(wtime as i64, winc as i64)
-        } else {
-            self.calculate_movetime_simple_side(btime as i64, binc as i64)
-        };
-        time_left_move
+    /// Threefold repetition of the game, for adjudication outside of the search.
+    pub fn is_threefold_repetition(&self) -> bool {
+        self.is_repetition(0)
     }
 
-    pub fn calculate_movetime_simple_side(&self, time_left: i64, inc: i64) -> u64 {
-        let mut time_left_move = time_left / 40 + inc / 2;
-        if time_left_move >= time_left {
-            time_left_move = time_left - 500;
+    /// Scans the reversible plies for the current position with the same side to move. An occurrence less than
+    /// `tree_plies` ago lies inside the search tree and counts as a draw on its own.
+    fn is_repetition(&self, tree_plies: usize) -> bool {
+        let mut occurrences = 0;
+        for plies_ago in (4..=self.reversible_plies()).step_by(2) {
+            if self.history[self.history.len() - plies_ago].zobrist == self.zobrist {
+                if plies_ago < tree_plies {
+                    return true;
+                }
+                occurrences += 1;
+                if occurrences == 2 {
+                    return true;
+                }
+            }
         }
+        false
+    }
 
-        if time_left_move < 0 {
-            time_left_move = inc / 2;
+    /// Whether a single reversible move leads back to a position of the search tree, so the side to move
+    /// can force at least a draw before the repetition is on the board.
+    pub fn has_upcoming_repetition(&self) -> bool {
+        let tree_plies = self.search_ply as usize;
+        let occupied = self.occupancy(WHITE) | self.occupancy(BLACK);
+        for plies_ago in (3..=min(self.reversible_plies(), tree_plies.saturating_sub(1))).step_by(2) {
+            let difference = ZobristHash(self.zobrist.0 ^ self.history[self.history.len() - plies_ago].zobrist.0);
+            if let Some((from, to)) = cuckoo_move(difference) {
+                let between = RAY_FROM_TO[from][to] & !(Bitboard::square(from) | Bitboard::square(to));
+                if (between & occupied).is_empty() {
+                    return true;
+                }
+            }
         }
-        time_left_move as u64
+        false
     }
 
-    fn midgame_scale(&self) -> f64 {
+    /// Plies back to the last capture, pawn move or null move, positions before that can't repeat.
+    fn reversible_plies(&self) -> usize {
+        let limit = min(self.fifty_move_rule as usize, self.history.len());
+        (1..=limit)
+            .find(|&plies_ago| self.history[self.history.len() - plies_ago].r#move == NULLMOVE)
+            .map_or(limit, |null_move_ply| null_move_ply - 1)
+    }
+
+    pub fn midgame_scale(&self) -> f64 {
         let scale =  -0.00006_f64 * ((self.phase() as f64 - 64_f64) * (self.phase() as f64 - 64_f64)) + 1.4_f64;
         if scale < 0.6_f64 {
             0.6_f64
@@ -355,7 +700,7 @@ impl GameState {
         }
     }
 
-    fn moves_left(&self) -> u16 {
+    pub fn moves_left(&self) -> u16 {
         ((self.phase() as f64) * (-40.0_f64 / 256.0_f64) + 55.0_f64) as u16
     }
 
@@ -386,6 +731,7 @@ impl GameState {
 
 }
 
+#[derive(Clone)]
 pub struct EvalParams {
     pub mg_piece_value: [Eval; NUM_OF_PIECES],
     pub eg_piece_value: [Eval; NUM_OF_PIECES],
@@ -401,13 +747,54 @@ pub struct EvalParams {
     pub eg_queen_mobility: [Eval; 28],
     pub mg_passed: [Eval; 8],
     pub eg_passed: [Eval; 8],
+    /// Per square of king distance to the stop square of an advanced passed pawn, weighted by its rank.
+    pub eg_passed_own_king_distance: Eval,
+    pub eg_passed_enemy_king_distance: Eval,
     pub open_king_file_punish_mg: Eval,
+    pub half_open_king_file_punish_mg: Eval,
+    /// Attack units per attacked king zone square, by attacking piece.
+    pub king_attack_weight: [Eval; NUM_OF_PIECES],
+    /// Penalty by the attack units of all pieces attacking the king zone.
+    pub king_safety_table: [Eval; 100],
+    /// Bonus of the closest own pawn in front of the king by its relative rank, index 0 is a missing pawn.
+    pub king_shelter: [Eval; 8],
+    /// Penalty of the closest enemy pawn in front of the king by its relative rank, index 0 is a missing pawn.
+    pub king_storm: [Eval; 8],
     pub mg_isolated_penalty: Eval,
     pub eg_isolated_penalty: Eval,
     pub mg_doubled_penalty: Eval,
     pub eg_doubled_penalty: Eval,
     pub mg_supported_bonus: Eval,
     pub eg_supported_bonus: Eval,
+    pub mg_bishop_pair: Eval,
+    pub eg_bishop_pair: Eval,
+    pub mg_rook_semi_open_file: Eval,
+    pub eg_rook_semi_open_file: Eval,
+    pub mg_rook_open_file: Eval,
+    pub eg_rook_open_file: Eval,
+    pub mg_queen_semi_open_file: Eval,
+    pub eg_queen_semi_open_file: Eval,
+    pub mg_queen_open_file: Eval,
+    pub eg_queen_open_file: Eval,
+    pub mg_rook_on_seventh: Eval,
+    pub eg_rook_on_seventh: Eval,
+    pub mg_queen_on_seventh: Eval,
+    pub eg_queen_on_seventh: Eval,
+    pub mg_knight_outpost: Eval,
+    pub eg_knight_outpost: Eval,
+    pub mg_bishop_outpost: Eval,
+    pub eg_bishop_outpost: Eval,
+    /// Per enemy piece we attack that isn't defended.
+    pub mg_hanging: Eval,
+    pub eg_hanging: Eval,
+    /// Per enemy piece attacked by one of our pawns.
+    pub mg_pawn_threat: Eval,
+    pub eg_pawn_threat: Eval,
+    /// Per enemy rook or queen attacked by one of our minors.
+    pub mg_minor_threat: Eval,
+    pub eg_minor_threat: Eval,
+    /// Per safe centre square on our side of the board.
+    pub mg_space: Eval,
 }
 
 pub static mut EVAL_PARAMS: EvalParams = EvalParams {
@@ -545,61 +932,170 @@ pub static mut EVAL_PARAMS: EvalParams = EvalParams {
     eg_knight_mobility: [-81,-56,-31,-16,5,11,17,20,25],
     mg_queen_mobility: [-30,-12,-8,-9,20,23,23,35,38,53,64,65,65,66,67,67,72,72,77,79,93,108,108,108,110,114,114,116],
     eg_queen_mobility: [-48,-30,-7,19,40,55,59,75,78,96,96,100,121,127,131,133,136,141,147,150,151,168,168,171,182,182,192,219],
-    open_king_file_punish_mg: 50,
+    open_king_file_punish_mg: 30,
+    half_open_king_file_punish_mg: 10,
+    king_attack_weight: [0, 3, 2, 2, 5, 0],
+    king_safety_table: [
+          0,   0,   1,   2,   3,   5,   7,   9,  12,  15,
+         18,  22,  26,  30,  35,  39,  44,  50,  56,  62,
+         68,  75,  82,  85,  89,  97, 105, 113, 122, 131,
+        140, 150, 169, 180, 191, 202, 213, 225, 237, 248,
+        260, 272, 283, 295, 307, 319, 330, 342, 354, 366,
+        377, 389, 401, 412, 424, 436, 448, 459, 471, 483,
+        494, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+        500, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+        500, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+        500, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+    ],
+    king_shelter: [-25, 25, 18, 5, 0, 0, 0, 0],
+    king_storm: [0, 45, 40, 20, 8, 0, 0, 0],
     mg_passed: [0, 10,17,15,62,168,276, 0],
     eg_passed: [0, 28,33,41,72,177,260, 0],
+    eg_passed_own_king_distance: 4,
+    eg_passed_enemy_king_distance: 10,
     mg_isolated_penalty: 23,
     eg_isolated_penalty: 12,
     mg_doubled_penalty: 28,
     eg_doubled_penalty: 36,
     mg_supported_bonus: 21,
     eg_supported_bonus: 21,
+    mg_bishop_pair: 30,
+    eg_bishop_pair: 50,
+    mg_rook_semi_open_file: 12,
+    eg_rook_semi_open_file: 6,
+    mg_rook_open_file: 25,
+    eg_rook_open_file: 10,
+    mg_queen_semi_open_file: 3,
+    eg_queen_semi_open_file: 4,
+    mg_queen_open_file: 6,
+    eg_queen_open_file: 6,
+    mg_rook_on_seventh: 15,
+    eg_rook_on_seventh: 30,
+    mg_queen_on_seventh: 8,
+    eg_queen_on_seventh: 15,
+    mg_knight_outpost: 25,
+    eg_knight_outpost: 15,
+    mg_bishop_outpost: 12,
+    eg_bishop_outpost: 6,
+    mg_hanging: 30,
+    eg_hanging: 20,
+    mg_pawn_threat: 50,
+    eg_pawn_threat: 40,
+    mg_minor_threat: 30,
+    eg_minor_threat: 25,
+    mg_space: 2,
 };
 
-pub fn relevant_eval_params() -> Vec<*mut Eval> {
-    let mut params = vec![];
-    unsafe {
-        params.push(std::ptr::addr_of_mut!(EVAL_PARAMS.mg_isolated_penalty));
-        params.push(std::ptr::addr_of_mut!(EVAL_PARAMS.eg_isolated_penalty));
-        params.push(std::ptr::addr_of_mut!(EVAL_PARAMS.mg_supported_bonus));
-        params.push(std::ptr::addr_of_mut!(EVAL_PARAMS.eg_supported_bonus));
-        params.push(std::ptr::addr_of_mut!(EVAL_PARAMS.mg_doubled_penalty));
-        params.push(std::ptr::addr_of_mut!(EVAL_PARAMS.eg_doubled_penalty));
-        for n in 0..EVAL_PARAMS.mg_piece_value.len() - 1 {
-            params.push(std::ptr::addr_of_mut!(EVAL_PARAMS.mg_piece_value[n]));
-            params.push(std::ptr::addr_of_mut!(EVAL_PARAMS.eg_piece_value[n]));
-        }
-        for n in 1..EVAL_PARAMS.mg_passed.len() - 1 {
-            params.push(std::ptr::addr_of_mut!(EVAL_PARAMS.mg_passed[n]));
-            params.push(std::ptr::addr_of_mut!(EVAL_PARAMS.eg_passed[n]));
-        }
-    }
-    params
+/// Whether `params` are `EVAL_PARAMS`, whose material and pawn scores the game state and pawn hash already hold.
+fn is_engine_params(params: &EvalParams) -> bool {
+    std::ptr::eq(params, std::ptr::addr_of!(EVAL_PARAMS))
 }
 
-pub fn print_relevant_params() {
-    print!("MG_VALUE:");
-    unsafe {
-        for value in EVAL_PARAMS.mg_piece_value {
-            print!(" {}", value);
-        }
-        println!();
-        print!("EG_VALUE:");
-        for value in EVAL_PARAMS.eg_piece_value {
-            print!(" {}", value);
-        }
-        println!();
-        print!("MG_PASSED:");
-        for value in EVAL_PARAMS.mg_passed {
-            print!(" {}", value);
-        }
-        println!();
-        print!("EG_PASSED:");
-        for value in EVAL_PARAMS.eg_passed {
-            print!(" {}", value);
+/// Rank of `square` as seen from `side`, 0 is its back rank.
+pub fn relative_rank(square: Square, side: Side) -> usize {
+    if side == WHITE {
+        square / 8
+    } else {
+        7 - square / 8
+    }
+}
+
+/// The parameters adjusted by texel tuning in tuning order, as named groups of entries.
+pub const TUNED_PARAMS: [(&str, fn(&mut EvalParams) -> &mut [Eval]); 43] = [
+    ("mg_isolated_penalty", |params| slice::from_mut(&mut params.mg_isolated_penalty)),
+    ("eg_isolated_penalty", |params| slice::from_mut(&mut params.eg_isolated_penalty)),
+    ("mg_supported_bonus", |params| slice::from_mut(&mut params.mg_supported_bonus)),
+    ("eg_supported_bonus", |params| slice::from_mut(&mut params.eg_supported_bonus)),
+    ("mg_doubled_penalty", |params| slice::from_mut(&mut params.mg_doubled_penalty)),
+    ("eg_doubled_penalty", |params| slice::from_mut(&mut params.eg_doubled_penalty)),
+    // Every piece but the king.
+    ("mg_piece_value", |params| &mut params.mg_piece_value[..KING]),
+    ("eg_piece_value", |params| &mut params.eg_piece_value[..KING]),
+    // Passed pawns can't stand on the first and last rank.
+    ("mg_passed", |params| &mut params.mg_passed[1..7]),
+    ("eg_passed", |params| &mut params.eg_passed[1..7]),
+    ("eg_passed_own_king_distance", |params| slice::from_mut(&mut params.eg_passed_own_king_distance)),
+    ("eg_passed_enemy_king_distance", |params| slice::from_mut(&mut params.eg_passed_enemy_king_distance)),
+    ("open_king_file_punish_mg", |params| slice::from_mut(&mut params.open_king_file_punish_mg)),
+    ("half_open_king_file_punish_mg", |params| slice::from_mut(&mut params.half_open_king_file_punish_mg)),
+    ("king_attack_weight", |params| &mut params.king_attack_weight[ROOK..=QUEEN]),
+    ("king_safety_table", |params| &mut params.king_safety_table),
+    ("king_shelter", |params| &mut params.king_shelter[..5]),
+    ("king_storm", |params| &mut params.king_storm[..5]),
+    ("mg_bishop_pair", |params| slice::from_mut(&mut params.mg_bishop_pair)),
+    ("eg_bishop_pair", |params| slice::from_mut(&mut params.eg_bishop_pair)),
+    ("mg_rook_semi_open_file", |params| slice::from_mut(&mut params.mg_rook_semi_open_file)),
+    ("eg_rook_semi_open_file", |params| slice::from_mut(&mut params.eg_rook_semi_open_file)),
+    ("mg_rook_open_file", |params| slice::from_mut(&mut params.mg_rook_open_file)),
+    ("eg_rook_open_file", |params| slice::from_mut(&mut params.eg_rook_open_file)),
+    ("mg_queen_semi_open_file", |params| slice::from_mut(&mut params.mg_queen_semi_open_file)),
+    ("eg_queen_semi_open_file", |params| slice::from_mut(&mut params.eg_queen_semi_open_file)),
+    ("mg_queen_open_file", |params| slice::from_mut(&mut params.mg_queen_open_file)),
+    ("eg_queen_open_file", |params| slice::from_mut(&mut params.eg_queen_open_file)),
+    ("mg_rook_on_seventh", |params| slice::from_mut(&mut params.mg_rook_on_seventh)),
+    ("eg_rook_on_seventh", |params| slice::from_mut(&mut params.eg_rook_on_seventh)),
+    ("mg_queen_on_seventh", |params| slice::from_mut(&mut params.mg_queen_on_seventh)),
+    ("eg_queen_on_seventh", |params| slice::from_mut(&mut params.eg_queen_on_seventh)),
+    ("mg_knight_outpost", |params| slice::from_mut(&mut params.mg_knight_outpost)),
+    ("eg_knight_outpost", |params| slice::from_mut(&mut params.eg_knight_outpost)),
+    ("mg_bishop_outpost", |params| slice::from_mut(&mut params.mg_bishop_outpost)),
+    ("eg_bishop_outpost", |params| slice::from_mut(&mut params.eg_bishop_outpost)),
+    ("mg_hanging", |params| slice::from_mut(&mut params.mg_hanging)),
+    ("eg_hanging", |params| slice::from_mut(&mut params.eg_hanging)),
+    ("mg_pawn_threat", |params| slice::from_mut(&mut params.mg_pawn_threat)),
+    ("eg_pawn_threat", |params| slice::from_mut(&mut params.eg_pawn_threat)),
+    ("mg_minor_threat", |params| slice::from_mut(&mut params.mg_minor_threat)),
+    ("eg_minor_threat", |params| slice::from_mut(&mut params.eg_minor_threat)),
+    ("mg_space", |params| slice::from_mut(&mut params.mg_space)),
+];
+
+impl EvalParams {
+    /// The `index`th entry of `TUNED_PARAMS`, `None` past the last one.
+    pub fn tuned_param_mut(&mut self, index: usize) -> Option<&mut Eval> {
+        let mut index = index;
+        for (_, group) in TUNED_PARAMS {
+            let length = group(self).len();
+            if index < length {
+                return group(self).get_mut(index);
+            }
+            index -= length;
         }
-        println!();
-        println!("ISOLATED (MG, EG): {} {}", EVAL_PARAMS.mg_isolated_penalty, EVAL_PARAMS.eg_isolated_penalty);
-        println!("DOUBLED (MG, EG): {} {}", EVAL_PARAMS.mg_doubled_penalty, EVAL_PARAMS.eg_doubled_penalty);
+        None
+    }
+}
+
+pub fn print_relevant_params(params: &EvalParams) {
+    print!("MG_VALUE:");
+    for value in params.mg_piece_value {
+        print!(" {}", value);
+    }
+    println!();
+    print!("EG_VALUE:");
+    for value in params.eg_piece_value {
+        print!(" {}", value);
+    }
+    println!();
+    print!("MG_PASSED:");
+    for value in params.mg_passed {
+        print!(" {}", value);
+    }
+    println!();
+    print!("EG_PASSED:");
+    for value in params.eg_passed {
+        print!(" {}", value);
     }
+    println!();
+    println!("PASSED KING DISTANCE (OWN, ENEMY): {} {}", params.eg_passed_own_king_distance, params.eg_passed_enemy_king_distance);
+    println!("ISOLATED (MG, EG): {} {}", params.mg_isolated_penalty, params.eg_isolated_penalty);
+    println!("DOUBLED (MG, EG): {} {}", params.mg_doubled_penalty, params.eg_doubled_penalty);
+    println!("KING FILE (OPEN, HALF OPEN): {} {}", params.open_king_file_punish_mg, params.half_open_king_file_punish_mg);
+    println!("KING ATTACK WEIGHT: {:?}", params.king_attack_weight);
+    println!("KING SAFETY TABLE: {:?}", params.king_safety_table);
+    println!("KING SHELTER: {:?}", params.king_shelter);
+    println!("KING STORM: {:?}", params.king_storm);
+    println!("BISHOP PAIR (MG, EG): {} {}", params.mg_bishop_pair, params.eg_bishop_pair);
+    println!("ROOK FILE (SEMI OPEN, OPEN): {} {} {} {}", params.mg_rook_semi_open_file, params.eg_rook_semi_open_file, params.mg_rook_open_file, params.eg_rook_open_file);
+    println!("OUTPOST (KNIGHT, BISHOP): {} {} {} {}", params.mg_knight_outpost, params.eg_knight_outpost, params.mg_bishop_outpost, params.eg_bishop_outpost);
+    println!("THREATS (HANGING, PAWN, MINOR): {} {} {} {} {} {}", params.mg_hanging, params.eg_hanging, params.mg_pawn_threat, params.eg_pawn_threat, params.mg_minor_threat, params.eg_minor_threat);
+    println!("SPACE: {}", params.mg_space);
 }
\ No newline at end of file
@@ -1,4 +1,5 @@
-use crate::eval::{MATERIAL_VALUE, PHASE_WEIGHT, EVAL_PARAMS};
+use crate::eval::{PHASE_WEIGHT, EVAL_PARAMS};
+use crate::nnue::{self, Accumulator};
 use crate::movegen::{CASTLE_WHITE_QUEENSIDE_CHECK_FREE, CASTLE_WHITE_KINGSIDE_CHECK_FREE, CASTLE_BLACK_QUEENSIDE_CHECK_FREE, CASTLE_BLACK_KINGSIDE_CHECK_FREE};
 use crate::smpsearch::{Eval, NULLMOVE};
 use crate::bitboard::{Bitboard, Square};
@@ -53,6 +54,8 @@ pub struct GameState {
     pub castling_rights: [bool; 4],
     pub fifty_move_rule: Ply,
     pub zobrist: ZobristHash,
+    /// Hash of the pawns alone, the key of the pawn hash table.
+    pub pawn_zobrist: ZobristHash,
     pub history: Vec<History>,
     // Eval
     pub material: [Eval; NUM_OF_PLAYERS],
@@ -62,6 +65,7 @@ pub struct GameState {
     pub psqt_eg: [Eval; NUM_OF_PLAYERS],
     pub has_castled: [bool; NUM_OF_PLAYERS],
     pub search_ply: u8,
+    pub accumulator: Accumulator,
 }
 
 impl GameState {
@@ -137,9 +141,15 @@ impl GameState {
             }
         }
 
+        state.refresh_accumulator(nnue::network());
         state
     }
 
+    /// Sets up the NNUE accumulator for `network`, `None` keeps the classical eval.
+    pub fn refresh_accumulator(&mut self, network: Option<&'static nnue::Network>) {
+        self.accumulator.refresh_all(network, &self.piece_boards);
+    }
+
     pub fn to_reduced_book_fen(&self) -> String {
         let mut fen_string = String::new();
 
@@ -324,19 +334,10 @@ impl GameState {
 
     }
 
-    pub fn find_first_legal_move(&mut self) -> Move {
-        for r#move in self.generate_pseudo_legal_moves() {
-            if self.apply_pseudo_legal_move(r#move) {
-                self.undo_move();
-                return r#move;
-            }
-        }
-        unreachable!()
-    }
-
     pub fn make_null_move(&mut self) {
         self.history.push(History { r#move: Move::new_from_to(0, 0, 0), fifty_move_rule: self.fifty_move_rule, castling_rights: self.castling_rights, zobrist: self.zobrist, en_passant: self.en_passant_board });
         self.plys += 1;
+        self.search_ply += 1;
         if self.en_passant_board != Bitboard(0) {
             self.zobrist.remove_en_passant_square(self.en_passant_board.next_piece_index());
             self.en_passant_board = Bitboard(0);
@@ -346,6 +347,7 @@ impl GameState {
 
     pub fn undo_null_move(&mut self) {
         self.plys -= 1;
+        self.search_ply -= 1;
         let history = self.history.pop().unwrap();
         self.zobrist = history.zobrist;
         self.fifty_move_rule = history.fifty_move_rule;
@@ -442,7 +444,7 @@ impl GameState {
         }
         else {
             self.en_passant_board = Bitboard::square(to + 8);
-            self.zobrist.add_en_passant_square(to + 9)
+            self.zobrist.add_en_passant_square(to + 8);
         }
     }
 
@@ -551,7 +553,7 @@ impl GameState {
         if self.piece_boards[WHITE][PAWN].is_empty() && self.piece_boards[BLACK][PAWN].is_empty() && self.is_material_draw() {
             return true;
         }
-        if self.fifty_move_rule >= 100 || self.has_repitition() {
+        if self.fifty_move_rule >= 100 || self.is_threefold_repetition() {
             return true;
         }
         for r#move in moves {
@@ -563,16 +565,6 @@ impl GameState {
         true
     }
 
-    pub fn unavoidable_game_over(&mut self) -> bool {
-        for r#move in self.generate_pseudo_legal_moves() {
-            if self.apply_pseudo_legal_move(r#move) {
-                self.undo_move();
-                return false;
-            }
-        }
-        true
-    }
-
     pub fn dump_panic_debug(&self) {
         println!("\nFen_str: {}\n", self.to_reduced_book_fen());
         print!("Move history: ");
@@ -593,6 +585,9 @@ impl GameState {
     fn add_piece(&mut self, square: Square, piece: Piece, side: Side) {
         self.piece_boards[side][piece].add_piece(square);
         self.zobrist.add_piece(square, piece, side);
+        if piece == PAWN {
+            self.pawn_zobrist.add_piece(square, piece, side);
+        }
         unsafe {
             let psqt_square = normalize_psqt_square(square, side);
             self.psqt_mg[side] += EVAL_PARAMS.psqt_mg[piece][psqt_square];
@@ -601,12 +596,16 @@ impl GameState {
             self.material_eg[side] += EVAL_PARAMS.eg_piece_value[piece];
         }
         self.phase -= PHASE_WEIGHT[piece];
+        self.accumulator.add_piece(&self.piece_boards, square, piece, side);
     }
 
     #[inline(always)]
     fn remove_piece(&mut self, square: Square, piece: Piece, side: Side) {
         self.piece_boards[side][piece].remove_piece(square);
         self.zobrist.remove_piece(square, piece, side);
+        if piece == PAWN {
+            self.pawn_zobrist.remove_piece(square, piece, side);
+        }
         unsafe {
             let psqt_square = normalize_psqt_square(square, side);
             self.psqt_mg[side] -= EVAL_PARAMS.psqt_mg[piece][psqt_square];
@@ -615,6 +614,7 @@ impl GameState {
             self.material_eg[side] -= EVAL_PARAMS.eg_piece_value[piece];
         }
         self.phase += PHASE_WEIGHT[piece];
+        self.accumulator.remove_piece(square, piece, side);
     }
 
     #[inline(always)]
@@ -701,7 +701,7 @@ fn rank_file_to_square(file: Square, rank: Square) -> Square {
 }
 
 #[inline(always)]
-fn normalize_psqt_square(square: Square, side: Side) -> Square {
+pub fn normalize_psqt_square(square: Square, side: Side) -> Square {
     if side == WHITE {
         square
     } else {
@@ -709,7 +709,7 @@ fn normalize_psqt_square(square: Square, side: Side) -> Square {
     }
 }
 
-fn piece_to_char(side: Side, piece: Piece) -> char {
+pub fn piece_to_char(side: Side, piece: Piece) -> char {
     let piece = match piece {
         PAWN => 'p',
         ROOK => 'r',
@@ -738,7 +738,15 @@ pub struct History {
 #[cfg(test)]
 mod tests {
 
-    use super::GameState;
+    use super::{GameState, PAWN, WHITE};
+    use crate::eval::{EvalTrace, EVAL_PARAMS};
+
+    fn play(state: &mut GameState, moves: &str) {
+        for algebraic in moves.split_whitespace() {
+            let r#move = state.generate_legal_moves().into_iter().find(|m| m.to_algebraic() == algebraic).unwrap();
+            state.apply_legal_move(r#move);
+        }
+    }
 
     #[test]
     fn test_do_undo_move() {
@@ -754,4 +762,92 @@ mod tests {
             }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_threefold_repetition() {
+        let mut state = GameState::new_starting_pos();
+        play(&mut state, "g1f3 g8f6 f3g1 f6g8");
+        state.search_ply = 0;
+        assert!(!state.is_threefold_repetition());
+        assert!(!state.has_repitition());
+        play(&mut state, "g1f3 g8f6 f3g1 f6g8");
+        state.search_ply = 0;
+        assert!(state.is_threefold_repetition());
+        assert!(state.has_repitition());
+    }
+
+    #[test]
+    fn test_twofold_repetition_in_search_tree() {
+        let mut state = GameState::new_starting_pos();
+        play(&mut state, "e2e4 e7e5");
+        state.search_ply = 0;
+        play(&mut state, "g1f3 g8f6 f3g1 f6g8");
+        // Back at the root position, which has only occurred twice.
+        assert!(!state.has_repitition());
+        assert!(state.has_upcoming_repetition());
+        play(&mut state, "g1f3");
+        assert!(state.has_repitition());
+        assert!(!state.is_threefold_repetition());
+    }
+
+    #[test]
+    fn test_repetition_before_pawn_move() {
+        let mut state = GameState::new_starting_pos();
+        play(&mut state, "g1f3 g8f6 f3g1 f6g8 g1f3 g8f6 f3g1 f6g8 e2e4");
+        state.search_ply = 0;
+        assert!(!state.has_upcoming_repetition());
+        play(&mut state, "e7e5 g1f3 g8f6 f3g1 f6g8");
+        state.search_ply = 0;
+        assert!(!state.is_threefold_repetition());
+    }
+
+    #[test]
+    fn test_pawn_zobrist() {
+        let mut state = GameState::new_starting_pos();
+        play(&mut state, "e2e4 d7d5 e4d5 g8f6");
+        let from_fen = GameState::new_from_fen("rnbqkb1r/ppp1pppp/5n2/3P4/8/8/PPPP1PPP/RNBQKBNR w KQkq - 1 3");
+        assert_eq!(state.pawn_zobrist, from_fen.pawn_zobrist);
+        for _ in 0..4 {
+            state.undo_move();
+        }
+        assert_eq!(state.pawn_zobrist, GameState::new_starting_pos().pawn_zobrist);
+    }
+
+    #[test]
+    fn test_eval_trace_sums_to_static_eval() {
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "2r3k1/pp3ppp/2n1b3/q2pP3/3P4/P1PB1N2/5PPP/R2Q1RK1 b - - 0 18",
+        ] {
+            let state = GameState::new_from_fen(fen);
+            let mut trace = EvalTrace::default();
+            let eval = state.static_eval_traced(&mut trace);
+            let white_eval = if state.side_to_move() == WHITE { eval } else { -eval };
+            assert_eq!(trace.total(state.phase()), white_eval);
+            assert_eq!(eval, state.static_eval());
+        }
+    }
+
+    #[test]
+    fn test_static_eval_with_params() {
+        let state = GameState::new_from_fen("2r3k1/pp3ppp/2n1b3/q2pP3/3P4/P1PB1N2/5PPP/R2Q1RK1 b - - 0 18");
+        // A copy is summed up from the board and bypasses the pawn hash, it has to agree with the incremental eval.
+        let mut params = unsafe { EVAL_PARAMS.clone() };
+        assert_eq!(state.static_eval_with(&params), state.static_eval());
+        // Black is a pawn down, every pawn is worth more to white.
+        params.mg_piece_value[PAWN] += 10;
+        params.eg_piece_value[PAWN] += 10;
+        assert_eq!(state.static_eval_with(&params), state.static_eval() - 10);
+    }
+
+    #[test]
+    fn test_passed_pawn_king_distance() {
+        // The black king is four squares away from the stop square of e6, the white king is next to it.
+        let state = GameState::new_from_fen("k6r/8/4P3/3K4/8/8/8/7R w - - 0 1");
+        let mut params = unsafe { EVAL_PARAMS.clone() };
+        // Hashed passed pawns have to give the same bonus as freshly found ones.
+        assert_eq!(state.static_eval_with(&params), state.static_eval());
+        params.eg_passed_enemy_king_distance += 10;
+        assert!(state.static_eval_with(&params) > state.static_eval());
+    }
+}
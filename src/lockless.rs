@@ -1,6 +1,5 @@
-use crate::eval;
 use crate::gamestate::GameState;
-use crate::smpsearch::{INFINITY, ISMATE};
+use crate::smpsearch::{INFINITY, score_to_tt};
 
 use crate::{zobrist::ZobristHash, r#move::Move, smpsearch::Eval};
 
@@ -14,18 +13,15 @@ pub struct LockLessTransTable {
 
 impl LockLessTransTable {
     pub fn insert(&mut self, key: ZobristHash, state: &GameState, eval: Eval, r#move: Move, flag: LockLessFlag, depth: u8) {
-        let eval = if eval > ISMATE {
-            eval + state.search_ply as Eval
-        } else if eval < -ISMATE {
-            eval - state.search_ply as Eval
-        } else {
-            eval
-        };
+        let eval = score_to_tt(eval, state.search_ply as usize);
         let value = LockLessValue::new(r#move, flag, eval, depth);
         let index = key.0 as usize % self.buckets.len();
         let old_val = self.buckets[index];
+        // Entries of the current search are only replaced by the same position or a deeper search,
+        // so the quiescence search can't push out the main search results.
         if old_val.key.0 == 0
-        || self.ages[index] <= self.current_age
+        || self.ages[index] != self.current_age
+        || old_val.key.0 ^ old_val.value.0 == key.0
         || old_val.value.depth() <= value.depth() {
             self.buckets[index].key = ZobristHash(key.0 ^ value.0);
             self.buckets[index].value = value;
@@ -58,7 +54,7 @@ impl LockLessTransTable {
     }
 
     pub fn advance_age(&mut self) {
-        self.current_age += 1;
+        self.current_age = self.current_age.wrapping_add(1);
     }
 }
 
@@ -95,6 +91,7 @@ impl LockLessValue {
     }
 }
 
+#[derive(PartialEq, Clone, Copy)]
 pub enum LockLessFlag {
     Alpha,
     Beta,
@@ -1,23 +1,12 @@
 #![feature(sync_unsafe_cell)]
 #![feature(adt_const_params)]
-#![feature(let_chains)]
 #![feature(lazy_cell)]
 #![feature(float_next_up_down)]
 
-use std::cell::SyncUnsafeCell;
 use std::env;
-use std::time::Duration;
 
 use book::OPENING_BOOK;
-use eval::relevant_eval_params;
-use gamestate::{GameState, ROOK};
-use lockless::{LockLessTransTable, LockLessValue};
 use movegen::RAY_FROM_TO;
-use r#move::Move;
-use smpsearch::INFINITY;
-use texel::{read_texel_sample_file, find_smallest_k, mean_square_error, optimize_params};
-use crate::texel::{generate_texel_sample, generate_texel_sample_threaded};
-use crate::uci::uci_loop;
 use crate::{magic::{BISHOP_MAGICS_AND_PLAYS, ROOK_MAGICS_AND_PLAYS}, movegen::{KING_MOVES, KNIGHT_MOVES}};
 
 #[macro_use]
@@ -37,6 +26,15 @@ mod lockless;
 mod smpsearch;
 mod book_data;
 mod smac;
+mod timeman;
+mod pawnhash;
+mod endgame;
+mod bitbase;
+mod nnue;
+mod train;
+mod packed;
+mod datagen;
+mod convert;
 
 fn initialize_lazy() {
     lazy_static::initialize(&RAY_FROM_TO);
@@ -45,26 +43,35 @@ fn initialize_lazy() {
     lazy_static::initialize(&ROOK_MAGICS_AND_PLAYS);
     lazy_static::initialize(&KNIGHT_MOVES);
     lazy_static::initialize(&OPENING_BOOK);
+    lazy_static::initialize(&bitbase::KPK_BITBASE);
 }
 
 fn main() {
     /* 
-    let all_fens = read_texel_sample_file();
-    let best_k = find_smallest_k(&all_fens);
+    let positions = texel::TexelPositions::load(&texel::read_texel_sample_file(), true);
+    let best_k = texel::find_smallest_k(&positions, unsafe { &eval::EVAL_PARAMS });
     */
 
     
-    // let params = relevant_eval_params();
-    // optimize_params(params);
+    // let mut params = unsafe { eval::EVAL_PARAMS.clone() };
+    // texel::optimize_params(&mut params, &positions);
     
 
     /*  
     env::set_var("RUST_BACKTRACE", "full");
     initialize_lazy();
-    uci_loop();
+    uci::uci_loop();
     */
     
-    // let texel_record = generate_texel_sample_threaded(64000, Duration::from_millis(60), 10);
+    // let texel_record = texel::generate_texel_sample_threaded(64000, std::time::Duration::from_millis(60), 10);
     
-    let _ = crate::smac::smac();
+    match env::args().nth(1).as_deref() {
+        Some("train") => train::train(),
+        Some("datagen") => datagen::datagen(),
+        Some("convert") => convert::convert(),
+        Some("shuffle") => convert::shuffle(),
+        _ => {
+            let _ = crate::smac::smac();
+        },
+    }
 }
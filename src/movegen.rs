@@ -3,6 +3,7 @@ use crate::gamestate::{GameState, KING, Side, Piece, PAWN, ROOK, KNIGHT, BISHOP,
 use crate::magic::{MAILBOX64, MAILBOX, BISHOP_MAGICS_AND_PLAYS, magic_index, ROOK_MAGICS_AND_PLAYS};
 use crate::r#move::{MoveList, self};
 use crate::r#move::Move;
+use crate::smpsearch::Eval;
 
 const FILE_A: usize = 0;
 const FILE_H: usize = 7;
@@ -11,6 +12,11 @@ const RANK_8: usize = 7;
 const RANK_1: usize = 0;
 const RANK_7: usize = 6;
 
+/// Piece values used by the static exchange evaluation, indexed by piece.
+static SEE_VALUE: [Eval; NUM_OF_PIECES] = [100, 500, 300, 300, 900, 0];
+/// Order in which attackers join an exchange, least valuable first.
+const SEE_ORDER: [Piece; NUM_OF_PIECES] = [PAWN, KNIGHT, BISHOP, ROOK, QUEEN, KING];
+
 pub const CASTLE_WHITE_QUEENSIDE_FREE: Bitboard       = Bitboard(0b00001110);
 pub const CASTLE_WHITE_QUEENSIDE_CHECK_FREE: Bitboard = Bitboard(0b00011100);
 pub const CASTLE_WHITE_KINGSIDE_FREE: Bitboard        = Bitboard(0b01100000);
@@ -48,22 +54,11 @@ impl GameState {
         let checkers = self.attackers_on_square(our_king_position, enemy_side, blockers);
         let num_checkers = checkers.0.count_ones();
 
-        let mut capture_mask = Bitboard::full();
-        let mut push_mask = Bitboard::full();
-
         if num_checkers > 1 {
             return moves;
         }
-        if num_checkers == 1 {
-            let checker_pos = checkers.next_piece_index();
-            capture_mask = checkers;
-            if is_slider(self.find_piece_on(checker_pos, enemy_side)) {
-                push_mask = RAY_FROM_TO[checker_pos][our_king_position];
-            } else {
-                push_mask = Bitboard(0);
-            }
-        }
 
+        let (capture_mask, push_mask) = self.check_evasion_masks(our_king_position, checkers, enemy_side);
         let evade_check_mask = capture_mask | push_mask;
 
         let pin_hv = self.get_hv_pinmask(our_king_position, blockers, enemy_side); 
@@ -639,6 +634,332 @@ impl GameState {
         move_list
     }
 
+    /// Generates all legal moves that get the side to move out of check.
+    /// Must only be called while in check.
+    pub fn generate_evasions(&mut self) -> MoveList {
+        let mut moves = MoveList::new();
+
+        let our_side = self.side_to_move();
+        let enemy_side = our_side ^ 1;
+        let our_occupancy = self.occupancy(our_side);
+        let enemy_occupancy = self.occupancy(enemy_side);
+        let blockers = our_occupancy | enemy_occupancy;
+        let king_danger_squares = self.king_danger_squares(our_side, blockers);
+
+        let our_king_position = self.piece_boards[our_side][KING].next_piece_index();
+        for to_square in KING_MOVES[our_king_position] & !king_danger_squares & !our_occupancy {
+            if enemy_occupancy.has(to_square) {
+                moves.add_move(Move::new_capture(our_king_position, to_square, KING, self.find_piece_on(to_square, enemy_side)));
+            } else {
+                moves.add_move(Move::new_from_to(our_king_position, to_square, KING));
+            }
+        }
+
+        let checkers = self.attackers_on_square(our_king_position, enemy_side, blockers);
+        debug_assert!(checkers.is_filled());
+        // Double check, only the king can move.
+        if checkers.piece_count() != 1 {
+            return moves;
+        }
+
+        let (capture_mask, push_mask) = self.check_evasion_masks(our_king_position, checkers, enemy_side);
+        let evade_check_mask = capture_mask | push_mask;
+
+        // A pinned piece can never block or capture a different checker.
+        let pinned = (self.get_hv_pinmask(our_king_position, blockers, enemy_side) | self.get_diagonal_pinmask(our_king_position, blockers, enemy_side)) & our_occupancy;
+
+        for from_square in self.piece_boards[our_side][KNIGHT] & !pinned {
+            for to_square in knight_move_bitboard(from_square) & evade_check_mask & !our_occupancy {
+                moves.add_move(self.quiet_or_capture(from_square, to_square, KNIGHT, enemy_occupancy, enemy_side));
+            }
+        }
+
+        for from_square in self.piece_boards[our_side][ROOK] & !pinned {
+            for to_square in rook_move_bitboard(from_square, blockers) & evade_check_mask & !our_occupancy {
+                moves.add_move(self.quiet_or_capture(from_square, to_square, ROOK, enemy_occupancy, enemy_side));
+            }
+        }
+
+        for from_square in self.piece_boards[our_side][BISHOP] & !pinned {
+            for to_square in bishop_move_bitboard(from_square, blockers) & evade_check_mask & !our_occupancy {
+                moves.add_move(self.quiet_or_capture(from_square, to_square, BISHOP, enemy_occupancy, enemy_side));
+            }
+        }
+
+        for from_square in self.piece_boards[our_side][QUEEN] & !pinned {
+            for to_square in queen_move_bitboard(from_square, blockers) & evade_check_mask & !our_occupancy {
+                moves.add_move(self.quiet_or_capture(from_square, to_square, QUEEN, enemy_occupancy, enemy_side));
+            }
+        }
+
+        let pawns = self.piece_boards[our_side][PAWN] & !pinned;
+        if our_side == WHITE {
+            let pawn_single_moves = (pawns << 8) & !blockers;
+            for to_square in pawn_single_moves & push_mask {
+                if RANK_BITMASK[RANK_8].has(to_square) {
+                    for piece in ROOK..=QUEEN {
+                        moves.add_move(Move::new_quiet_promotion(to_square - 8, to_square, piece));
+                    }
+                } else {
+                    moves.add_move(Move::new_from_to(to_square - 8, to_square, PAWN));
+                }
+            }
+            for to_square in ((((pawns & RANK_BITMASK[RANK_2]) << 8) & !blockers) << 8) & !blockers & push_mask {
+                moves.add_move(Move::new_double_pawn_push(to_square - 16, to_square));
+            }
+            let pawn_up_left_capture = ((pawns & !FILE_BITMASK[FILE_A]) << 7) & enemy_occupancy & capture_mask;
+            for to_square in pawn_up_left_capture {
+                self.add_pawn_capture(&mut moves, to_square - 7, to_square, enemy_side);
+            }
+            let pawn_up_right_capture = ((pawns & !FILE_BITMASK[FILE_H]) << 9) & enemy_occupancy & capture_mask;
+            for to_square in pawn_up_right_capture {
+                self.add_pawn_capture(&mut moves, to_square - 9, to_square, enemy_side);
+            }
+            if self.en_passant_board.is_filled() {
+                let en_passant_pos = self.en_passant_board.next_piece_index();
+                if (((pawns & !FILE_BITMASK[FILE_A]) << 7) & self.en_passant_board).is_filled() {
+                    self.add_legal_en_passant(&mut moves, Move::new_en_passant_capture(en_passant_pos - 7, en_passant_pos), our_king_position);
+                }
+                if (((pawns & !FILE_BITMASK[FILE_H]) << 9) & self.en_passant_board).is_filled() {
+                    self.add_legal_en_passant(&mut moves, Move::new_en_passant_capture(en_passant_pos - 9, en_passant_pos), our_king_position);
+                }
+            }
+        } else {
+            let pawn_single_moves = (pawns >> 8) & !blockers;
+            for to_square in pawn_single_moves & push_mask {
+                if RANK_BITMASK[RANK_1].has(to_square) {
+                    for piece in ROOK..=QUEEN {
+                        moves.add_move(Move::new_quiet_promotion(to_square + 8, to_square, piece));
+                    }
+                } else {
+                    moves.add_move(Move::new_from_to(to_square + 8, to_square, PAWN));
+                }
+            }
+            for to_square in ((((pawns & RANK_BITMASK[RANK_7]) >> 8) & !blockers) >> 8) & !blockers & push_mask {
+                moves.add_move(Move::new_double_pawn_push(to_square + 16, to_square));
+            }
+            let pawn_down_right_capture = ((pawns & !FILE_BITMASK[FILE_H]) >> 7) & enemy_occupancy & capture_mask;
+            for to_square in pawn_down_right_capture {
+                self.add_pawn_capture(&mut moves, to_square + 7, to_square, enemy_side);
+            }
+            let pawn_down_left_capture = ((pawns & !FILE_BITMASK[FILE_A]) >> 9) & enemy_occupancy & capture_mask;
+            for to_square in pawn_down_left_capture {
+                self.add_pawn_capture(&mut moves, to_square + 9, to_square, enemy_side);
+            }
+            if self.en_passant_board.is_filled() {
+                let en_passant_pos = self.en_passant_board.next_piece_index();
+                if (((pawns & !FILE_BITMASK[FILE_H]) >> 7) & self.en_passant_board).is_filled() {
+                    self.add_legal_en_passant(&mut moves, Move::new_en_passant_capture(en_passant_pos + 7, en_passant_pos), our_king_position);
+                }
+                if (((pawns & !FILE_BITMASK[FILE_A]) >> 9) & self.en_passant_board).is_filled() {
+                    self.add_legal_en_passant(&mut moves, Move::new_en_passant_capture(en_passant_pos + 9, en_passant_pos), our_king_position);
+                }
+            }
+        }
+
+        moves
+    }
+
+    /// Generates pseudo legal quiet moves that give check, either directly or by discovery.
+    /// Promotions and castling are left out.
+    pub fn generate_quiet_checks(&mut self) -> MoveList {
+        let mut move_list = MoveList::new();
+
+        let our_side = self.side_to_move();
+        let enemy_side = our_side ^ 1;
+        let our_occupancy = self.occupancy(our_side);
+        let enemy_occupancy = self.occupancy(enemy_side);
+        let blockers = our_occupancy | enemy_occupancy;
+        let empty = !blockers;
+
+        let enemy_king_position = self.piece_boards[enemy_side][KING].next_piece_index();
+        let knight_checks = knight_move_bitboard(enemy_king_position);
+        let bishop_checks = bishop_move_bitboard(enemy_king_position, blockers);
+        let rook_checks = rook_move_bitboard(enemy_king_position, blockers);
+        let pawn_checks = pawn_attack_bitboard(enemy_king_position, enemy_side);
+
+        // Our pieces standing between one of our sliders and the enemy king. Moving them might uncover a check.
+        let discoverers = (self.get_hv_pinmask(enemy_king_position, blockers, our_side) | self.get_diagonal_pinmask(enemy_king_position, blockers, our_side)) & our_occupancy;
+
+        for from_square in self.piece_boards[our_side][KNIGHT] {
+            let targets = knight_move_bitboard(from_square) & empty;
+            self.add_quiet_checks(&mut move_list, from_square, targets, knight_checks, KNIGHT, discoverers);
+        }
+
+        for from_square in self.piece_boards[our_side][ROOK] {
+            let targets = rook_move_bitboard(from_square, blockers) & empty;
+            self.add_quiet_checks(&mut move_list, from_square, targets, rook_checks, ROOK, discoverers);
+        }
+
+        for from_square in self.piece_boards[our_side][BISHOP] {
+            let targets = bishop_move_bitboard(from_square, blockers) & empty;
+            self.add_quiet_checks(&mut move_list, from_square, targets, bishop_checks, BISHOP, discoverers);
+        }
+
+        for from_square in self.piece_boards[our_side][QUEEN] {
+            let targets = queen_move_bitboard(from_square, blockers) & empty;
+            self.add_quiet_checks(&mut move_list, from_square, targets, rook_checks | bishop_checks, QUEEN, discoverers);
+        }
+
+        let king_position = self.piece_boards[our_side][KING].next_piece_index();
+        let king_targets = king_move_bitboard(king_position) & empty;
+        self.add_quiet_checks(&mut move_list, king_position, king_targets, Bitboard::empty(), KING, discoverers);
+
+        if our_side == WHITE {
+            for to_square in (self.piece_boards[our_side][PAWN] << 8) & empty & !RANK_BITMASK[RANK_8] {
+                self.add_quiet_checks(&mut move_list, to_square - 8, Bitboard::square(to_square), pawn_checks, PAWN, discoverers);
+            }
+            let pawn_double_moves = ((((self.piece_boards[our_side][PAWN] & RANK_BITMASK[RANK_2]) << 8) & empty) << 8) & empty;
+            for to_square in pawn_double_moves {
+                let r#move = Move::new_double_pawn_push(to_square - 16, to_square);
+                if pawn_checks.has(to_square) || (discoverers.has(to_square - 16) && self.gives_check(r#move)) {
+                    move_list.add_move(r#move);
+                }
+            }
+        } else {
+            for to_square in (self.piece_boards[our_side][PAWN] >> 8) & empty & !RANK_BITMASK[RANK_1] {
+                self.add_quiet_checks(&mut move_list, to_square + 8, Bitboard::square(to_square), pawn_checks, PAWN, discoverers);
+            }
+            let pawn_double_moves = ((((self.piece_boards[our_side][PAWN] & RANK_BITMASK[RANK_7]) >> 8) & empty) >> 8) & empty;
+            for to_square in pawn_double_moves {
+                let r#move = Move::new_double_pawn_push(to_square + 16, to_square);
+                if pawn_checks.has(to_square) || (discoverers.has(to_square + 16) && self.gives_check(r#move)) {
+                    move_list.add_move(r#move);
+                }
+            }
+        }
+
+        move_list
+    }
+
+    fn add_quiet_checks(&mut self, move_list: &mut MoveList, from_square: Square, targets: Bitboard, check_squares: Bitboard, piece: Piece, discoverers: Bitboard) {
+        if discoverers.has(from_square) {
+            for to_square in targets {
+                let r#move = Move::new_from_to(from_square, to_square, piece);
+                if check_squares.has(to_square) || self.gives_check(r#move) {
+                    move_list.add_move(r#move);
+                }
+            }
+        } else {
+            for to_square in targets & check_squares {
+                move_list.add_move(Move::new_from_to(from_square, to_square, piece));
+            }
+        }
+    }
+
+    /// Whether the pseudo legal `move` attacks the enemy king after it is played.
+    pub fn gives_check(&mut self, r#move: Move) -> bool {
+        let enemy_side = self.side_to_move() ^ 1;
+        self.apply_legal_move(r#move);
+        let enemy_king_position = self.piece_boards[enemy_side][KING].next_piece_index();
+        let gives_check = self.attackers_on_square(enemy_king_position, enemy_side ^ 1, self.occupancy(WHITE) | self.occupancy(BLACK)).is_filled();
+        self.undo_move();
+        gives_check
+    }
+
+    /// Static exchange evaluation. Whether the exchange sequence started by `move` on its target
+    /// square wins at least `threshold` centipawns for the side to move.
+    pub fn see_ge(&self, r#move: Move, threshold: Eval) -> bool {
+        if r#move.is_castle_and_where().is_some() {
+            return 0 >= threshold;
+        }
+        let from = r#move.from();
+        let to = r#move.to();
+
+        let mut swap = if r#move.is_capture() { SEE_VALUE[r#move.captured_piece()] } else { 0 } - threshold;
+        if swap < 0 {
+            return false;
+        }
+        swap = SEE_VALUE[r#move.moving_piece()] - swap;
+        if swap <= 0 {
+            return true;
+        }
+
+        let mut occupied = (self.occupancy(WHITE) | self.occupancy(BLACK)) & !Bitboard::square(from);
+        if r#move.is_capture_and_en_passant() {
+            occupied &= !Bitboard::square(if self.side_to_move() == WHITE { to - 8 } else { to + 8 });
+        }
+        let diagonal_sliders = self.piece_boards[WHITE][BISHOP] | self.piece_boards[BLACK][BISHOP] | self.piece_boards[WHITE][QUEEN] | self.piece_boards[BLACK][QUEEN];
+        let straight_sliders = self.piece_boards[WHITE][ROOK] | self.piece_boards[BLACK][ROOK] | self.piece_boards[WHITE][QUEEN] | self.piece_boards[BLACK][QUEEN];
+
+        let mut side = self.side_to_move();
+        let mut attackers = self.attackers_on_square(to, WHITE, occupied) | self.attackers_on_square(to, BLACK, occupied);
+        let mut result = true;
+        loop {
+            side ^= 1;
+            attackers &= occupied;
+            let side_attackers = attackers & self.occupancy(side);
+            if side_attackers.is_empty() {
+                break;
+            }
+            result = !result;
+
+            let Some(piece) = SEE_ORDER.into_iter().find(|&piece| (side_attackers & self.piece_boards[side][piece]).is_filled()) else {
+                unreachable!()
+            };
+            if piece == KING {
+                // The king may only recapture if the square is not defended anymore.
+                return if (attackers & self.occupancy(side ^ 1)).is_filled() { !result } else { result };
+            }
+            swap = SEE_VALUE[piece] - swap;
+            if swap < result as Eval {
+                break;
+            }
+            occupied ^= Bitboard::square((side_attackers & self.piece_boards[side][piece]).next_piece_index());
+            if piece == PAWN || piece == BISHOP || piece == QUEEN {
+                attackers |= bishop_move_bitboard(to, occupied) & diagonal_sliders;
+            }
+            if piece == ROOK || piece == QUEEN {
+                attackers |= rook_move_bitboard(to, occupied) & straight_sliders;
+            }
+        }
+        result
+    }
+
+    /// Returns the capture and push masks a non-king move has to hit to resolve a check by `checkers`.
+    /// Both masks are full if there is no checker.
+    fn check_evasion_masks(&self, king_square: Square, checkers: Bitboard, enemy_side: Side) -> (Bitboard, Bitboard) {
+        if checkers.is_empty() {
+            return (Bitboard::full(), Bitboard::full());
+        }
+        let checker_pos = checkers.next_piece_index();
+        let push_mask = if is_slider(self.find_piece_on(checker_pos, enemy_side)) {
+            RAY_FROM_TO[checker_pos][king_square]
+        } else {
+            Bitboard(0)
+        };
+        (checkers, push_mask)
+    }
+
+    #[inline(always)]
+    fn quiet_or_capture(&self, from_square: Square, to_square: Square, piece: Piece, enemy_occupancy: Bitboard, enemy_side: Side) -> Move {
+        if enemy_occupancy.has(to_square) {
+            Move::new_capture(from_square, to_square, piece, self.find_piece_on(to_square, enemy_side))
+        } else {
+            Move::new_from_to(from_square, to_square, piece)
+        }
+    }
+
+    fn add_pawn_capture(&self, moves: &mut MoveList, from_square: Square, to_square: Square, enemy_side: Side) {
+        let captured = self.find_piece_on(to_square, enemy_side);
+        if (RANK_BITMASK[RANK_1] | RANK_BITMASK[RANK_8]).has(to_square) {
+            for piece in ROOK..=QUEEN {
+                moves.add_move(Move::new_capture_promotion(from_square, to_square, piece, captured));
+            }
+        } else {
+            moves.add_move(Move::new_capture(from_square, to_square, PAWN, captured));
+        }
+    }
+
+    fn add_legal_en_passant(&mut self, moves: &mut MoveList, r#move: Move, our_king_position: Square) {
+        let enemy_side = self.side_to_move() ^ 1;
+        self.apply_legal_move(r#move);
+        if self.attackers_on_square(our_king_position, enemy_side, self.occupancy(WHITE) | self.occupancy(BLACK)).is_empty() {
+            moves.add_move(r#move);
+        }
+        self.undo_move();
+    }
+
     pub fn get_hv_pinmask(&self, king_square: Square, blockers: Bitboard, enemy_side: Side) -> Bitboard {
         let king_blockers = rook_move_bitboard(king_square, blockers);
         let without_king_blockers = blockers & !king_blockers;
@@ -784,6 +1105,17 @@ pub fn king_move_bitboard(square: Square) -> Bitboard {
     KING_MOVES[square]
 }
 
+/// Squares attacked by a pawn of `side` standing on `square`.
+#[inline(always)]
+pub fn pawn_attack_bitboard(square: Square, side: Side) -> Bitboard {
+    let square_bitboard = Bitboard::square(square);
+    if side == WHITE {
+        ((square_bitboard & !FILE_BITMASK[FILE_A]) << 7) | ((square_bitboard & !FILE_BITMASK[FILE_H]) << 9)
+    } else {
+        ((square_bitboard & !FILE_BITMASK[FILE_H]) >> 7) | ((square_bitboard & !FILE_BITMASK[FILE_A]) >> 9)
+    }
+}
+
 #[inline(always)]
 fn is_slider(piece: Piece) -> bool {
     piece > PAWN && piece < KING
@@ -883,6 +1215,74 @@ lazy_static! {
 mod tests {
     use crate::{gamestate::GameState, uci::perft};
 
+    fn sorted(moves: impl IntoIterator<Item = crate::r#move::Move>) -> Vec<u32> {
+        let mut moves: Vec<u32> = moves.into_iter().map(|r#move| r#move.0).collect();
+        moves.sort();
+        moves
+    }
+
+    #[test]
+    fn evasions_match_legal_moves() {
+        let fens = [
+            "rnbqkbnr/ppppp1pp/8/5p1Q/4P3/8/PPPP1PPP/RNB1KBNR b KQkq - 1 2",
+            "8/8/8/2k5/3Pp3/8/8/4K3 b - d3 0 1",
+            "4k3/8/8/8/7b/8/8/4K2r w - - 0 1",
+            "4k3/8/8/8/1b6/8/3N4/r3K3 w - - 0 1",
+            "3k4/8/8/8/8/8/3p4/4K2R w - - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBqPPP/R3K2R w KQkq - 0 1",
+        ];
+        for fen in fens {
+            let mut state = GameState::new_from_fen(fen);
+            assert!(state.is_in_check(), "{fen}");
+            assert_eq!(sorted(state.generate_evasions()), sorted(state.generate_legal_moves()), "{fen}");
+        }
+    }
+
+    #[test]
+    fn static_exchange_evaluation() {
+        use crate::r#move::Move;
+        use crate::gamestate::{ROOK, PAWN, QUEEN, BISHOP};
+        use crate::uci::algebraic_to_index;
+        let sq = |name| algebraic_to_index(name).unwrap();
+
+        let state = GameState::new_from_fen("4k3/8/8/3p4/8/8/8/3RK3 w - - 0 1");
+        assert!(state.see_ge(Move::new_capture(sq("d1"), sq("d5"), ROOK, PAWN), 100));
+        assert!(!state.see_ge(Move::new_capture(sq("d1"), sq("d5"), ROOK, PAWN), 101));
+
+        let state = GameState::new_from_fen("4k3/8/4p3/3p4/8/8/8/3RK3 w - - 0 1");
+        assert!(!state.see_ge(Move::new_capture(sq("d1"), sq("d5"), ROOK, PAWN), 0));
+
+        // The queen behind the rook joins the exchange through the x-ray.
+        let state = GameState::new_from_fen("3rk3/8/8/3p4/8/8/3R4/3QK3 w - - 0 1");
+        assert!(state.see_ge(Move::new_capture(sq("d2"), sq("d5"), ROOK, PAWN), 0));
+        assert!(!state.see_ge(Move::new_capture(sq("d2"), sq("d5"), ROOK, PAWN), 101));
+
+        // Quiet moves onto a square attacked by a pawn lose the piece.
+        let state = GameState::new_from_fen("4k3/8/8/3p4/8/8/8/2B1K2Q w - - 0 1");
+        assert!(!state.see_ge(Move::new_from_to(sq("h1"), sq("e4"), QUEEN), 0));
+        assert!(state.see_ge(Move::new_from_to(sq("c1"), sq("f4"), BISHOP), 0));
+    }
+
+    #[test]
+    fn quiet_checks_match_brute_force() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "4k3/8/8/8/4N3/8/4R3/4K3 w - - 0 1",
+            "4k3/4r3/8/4n3/8/8/3P4/4K3 b - - 0 1",
+            "4k3/8/8/8/8/8/3P4/B3K3 w - - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        ];
+        for fen in fens {
+            let mut state = GameState::new_from_fen(fen);
+            let quiets: Vec<_> = state.generate_pseudo_legal_moves().into_iter()
+                .filter(|r#move| !r#move.is_capture() && !r#move.is_promotion() && r#move.is_castle_and_where().is_none())
+                .collect();
+            let expected: Vec<_> = quiets.into_iter().filter(|&r#move| state.gives_check(r#move)).collect();
+            assert_eq!(sorted(state.generate_quiet_checks()), sorted(expected), "{fen}");
+        }
+    }
+
     #[test]
     fn perft_starting_pos() {
         let mut starting_pos = GameState::new_starting_pos();
@@ -0,0 +1,319 @@
+use std::fs;
+use std::io;
+
+use crate::bitboard::{Bitboard, Square};
+use crate::gamestate::{Piece, Side, BLACK, KING, NUM_OF_PIECES, NUM_OF_PLAYERS};
+use crate::smpsearch::Eval;
+
+/// Features of one king bucket: piece colour relative to the perspective, piece type and square.
+pub const FEATURES_PER_BUCKET: usize = NUM_OF_PLAYERS * NUM_OF_PIECES * 64;
+pub const KING_BUCKETS: usize = 4;
+pub const FEATURES: usize = KING_BUCKETS * FEATURES_PER_BUCKET;
+pub const HIDDEN: usize = 256;
+
+/// Quantisation of the feature transformer and the output layer, the clipped ReLU tops out at `QA`.
+pub const QA: i32 = 255;
+pub const QB: i32 = 64;
+/// Converts the network output into centipawns.
+pub const OUTPUT_SCALE: i32 = 400;
+
+/// Bucket of the king square, seen from its own side and mirrored onto files a-d.
+const KING_BUCKET: [usize; 32] = [
+    0, 0, 1, 1,
+    2, 2, 2, 2,
+    3, 3, 3, 3,
+    3, 3, 3, 3,
+    3, 3, 3, 3,
+    3, 3, 3, 3,
+    3, 3, 3, 3,
+    3, 3, 3, 3,
+];
+
+/// Network loaded by the `EvalFile` option, positions set up afterwards evaluate with it.
+static mut NETWORK: Option<&'static Network> = None;
+
+pub fn network() -> Option<&'static Network> {
+    unsafe { NETWORK }
+}
+
+/// Loads the network of `path`, an empty path goes back to the classical eval.
+pub fn set_network(path: &str) -> io::Result<()> {
+    let network = if path.is_empty() || path == "<empty>" {
+        None
+    } else {
+        // Leaked on purpose, positions hold on to the network they were set up with.
+        Some(&*Box::leak(Box::new(Network::load(path)?)))
+    };
+    unsafe {
+        NETWORK = network;
+    }
+    Ok(())
+}
+
+/// Quantised weights, stored in a file as little endian `i16`s in the order of the fields.
+#[derive(PartialEq)]
+pub struct Network {
+    pub feature_weights: Vec<i16>,
+    pub feature_bias: Vec<i16>,
+    /// Weights of the side to move's half of the hidden layer first, then the other half.
+    pub output_weights: Vec<i16>,
+    pub output_bias: i16,
+}
+
+impl Network {
+    pub const PARAMETERS: usize = FEATURES * HIDDEN + HIDDEN + 2 * HIDDEN + 1;
+
+    pub fn load(path: &str) -> io::Result<Self> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() != 2 * Self::PARAMETERS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("expected {} bytes of network weights, found {}", 2 * Self::PARAMETERS, bytes.len())));
+        }
+        let mut values = bytes.chunks_exact(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]]));
+        let mut take = |count: usize| values.by_ref().take(count).collect::<Vec<i16>>();
+        let feature_weights = take(FEATURES * HIDDEN);
+        let feature_bias = take(HIDDEN);
+        let output_weights = take(2 * HIDDEN);
+        let output_bias = take(1)[0];
+        Ok(Network { feature_weights, feature_bias, output_weights, output_bias })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.feature_weights.iter()
+            .chain(&self.feature_bias)
+            .chain(&self.output_weights)
+            .chain(std::iter::once(&self.output_bias))
+            .flat_map(|value| value.to_le_bytes())
+            .collect()
+    }
+
+    /// Centipawns for `side_to_move`. Each half of the dot product fits an `i32` for any weights, their scaled sum doesn't.
+    pub fn evaluate(&self, accumulator: &Accumulator, side_to_move: Side) -> Eval {
+        let output = crelu_dot(&accumulator.values[side_to_move], &self.output_weights[..HIDDEN]) as i64
+            + crelu_dot(&accumulator.values[side_to_move ^ 1], &self.output_weights[HIDDEN..]) as i64;
+        ((output + self.output_bias as i64 * QA as i64) * OUTPUT_SCALE as i64 / (QA * QB) as i64) as Eval
+    }
+
+    fn feature_column(&self, feature: usize) -> &[i16] {
+        &self.feature_weights[feature * HIDDEN..(feature + 1) * HIDDEN]
+    }
+}
+
+/// King bucket and the square transformation of `perspective` with its king on `king`.
+#[derive(Clone, Copy, PartialEq, Default)]
+pub struct Orientation {
+    bucket: usize,
+    flip: Square,
+}
+
+impl Orientation {
+    pub fn new(perspective: Side, king: Square) -> Self {
+        let vertical = if perspective == BLACK { 56 } else { 0 };
+        let horizontal = if (king ^ vertical) % 8 >= 4 { 7 } else { 0 };
+        let relative_king = king ^ vertical ^ horizontal;
+        Orientation { bucket: KING_BUCKET[relative_king / 8 * 4 + relative_king % 8], flip: vertical ^ horizontal }
+    }
+
+    pub fn feature(&self, perspective: Side, square: Square, piece: Piece, side: Side) -> usize {
+        let colour = (side != perspective) as usize;
+        self.bucket * FEATURES_PER_BUCKET + (colour * NUM_OF_PIECES + piece) * 64 + (square ^ self.flip)
+    }
+}
+
+/// First layer outputs of both perspectives, kept up to date by `GameState::add_piece` and `remove_piece`.
+#[derive(Clone, PartialEq)]
+pub struct Accumulator {
+    pub network: Option<&'static Network>,
+    pub values: [[i16; HIDDEN]; NUM_OF_PLAYERS],
+    orientations: [Orientation; NUM_OF_PLAYERS],
+}
+
+impl Default for Accumulator {
+    fn default() -> Self {
+        Accumulator { network: None, values: [[0; HIDDEN]; NUM_OF_PLAYERS], orientations: [Orientation::default(); NUM_OF_PLAYERS] }
+    }
+}
+
+impl Accumulator {
+    /// Recomputes both perspectives from the board.
+    pub fn refresh_all(&mut self, network: Option<&'static Network>, piece_boards: &[[Bitboard; NUM_OF_PIECES]; NUM_OF_PLAYERS]) {
+        self.network = network;
+        if network.is_some() {
+            for perspective in 0..NUM_OF_PLAYERS {
+                self.refresh(perspective, piece_boards);
+            }
+        }
+    }
+
+    fn refresh(&mut self, perspective: Side, piece_boards: &[[Bitboard; NUM_OF_PIECES]; NUM_OF_PLAYERS]) {
+        let Some(network) = self.network else {
+            return;
+        };
+        let orientation = Orientation::new(perspective, piece_boards[perspective][KING].next_piece_index());
+        self.orientations[perspective] = orientation;
+        let values = &mut self.values[perspective];
+        values.copy_from_slice(&network.feature_bias);
+        for (side, boards) in piece_boards.iter().enumerate() {
+            for (piece, &board) in boards.iter().enumerate() {
+                for square in board {
+                    add_column(values, network.feature_column(orientation.feature(perspective, square, piece, side)));
+                }
+            }
+        }
+    }
+
+    /// Adds the piece after it was put on the board, a king changing its orientation refreshes its own side.
+    pub fn add_piece(&mut self, piece_boards: &[[Bitboard; NUM_OF_PIECES]; NUM_OF_PLAYERS], square: Square, piece: Piece, side: Side) {
+        let Some(network) = self.network else {
+            return;
+        };
+        for perspective in 0..NUM_OF_PLAYERS {
+            if piece == KING && perspective == side && Orientation::new(side, square) != self.orientations[side] {
+                self.refresh(side, piece_boards);
+                continue;
+            }
+            let feature = self.orientations[perspective].feature(perspective, square, piece, side);
+            add_column(&mut self.values[perspective], network.feature_column(feature));
+        }
+    }
+
+    pub fn remove_piece(&mut self, square: Square, piece: Piece, side: Side) {
+        let Some(network) = self.network else {
+            return;
+        };
+        for perspective in 0..NUM_OF_PLAYERS {
+            let feature = self.orientations[perspective].feature(perspective, square, piece, side);
+            sub_column(&mut self.values[perspective], network.feature_column(feature));
+        }
+    }
+}
+
+/// Wraps like the SIMD lanes would. Trained weights never get near the `i16` range, but any file can be
+/// loaded, and wrapping updates still undo exactly.
+fn add_column(values: &mut [i16; HIDDEN], column: &[i16]) {
+    for (value, &weight) in values.iter_mut().zip(column) {
+        *value = value.wrapping_add(weight);
+    }
+}
+
+fn sub_column(values: &mut [i16; HIDDEN], column: &[i16]) {
+    for (value, &weight) in values.iter_mut().zip(column) {
+        *value = value.wrapping_sub(weight);
+    }
+}
+
+/// Dot product of the clipped ReLU of `values` with `weights`.
+fn crelu_dot(values: &[i16; HIDDEN], weights: &[i16]) -> i32 {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        return unsafe { crelu_dot_avx2(values, weights) };
+    }
+    crelu_dot_scalar(values, weights)
+}
+
+fn crelu_dot_scalar(values: &[i16; HIDDEN], weights: &[i16]) -> i32 {
+    values.iter().zip(weights).map(|(&value, &weight)| (value as i32).clamp(0, QA) * weight as i32).sum()
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn crelu_dot_avx2(values: &[i16; HIDDEN], weights: &[i16]) -> i32 {
+    use std::arch::x86_64::*;
+
+    assert!(weights.len() >= HIDDEN);
+    unsafe {
+        let zero = _mm256_setzero_si256();
+        let max = _mm256_set1_epi16(QA as i16);
+        let mut sum = _mm256_setzero_si256();
+        for index in (0..HIDDEN).step_by(16) {
+            let value = _mm256_loadu_si256(values.as_ptr().add(index) as *const __m256i);
+            let weight = _mm256_loadu_si256(weights.as_ptr().add(index) as *const __m256i);
+            let clipped = _mm256_min_epi16(_mm256_max_epi16(value, zero), max);
+            sum = _mm256_add_epi32(sum, _mm256_madd_epi16(clipped, weight));
+        }
+        let halves = _mm_add_epi32(_mm256_castsi256_si128(sum), _mm256_extracti128_si256(sum, 1));
+        let pairs = _mm_add_epi32(halves, _mm_shuffle_epi32(halves, 0b01_00_11_10));
+        let total = _mm_add_epi32(pairs, _mm_shuffle_epi32(pairs, 0b10_11_00_01));
+        _mm_cvtsi128_si32(total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::gamestate::GameState;
+    use crate::r#move::Move;
+
+    use super::{crelu_dot_scalar, Accumulator, Network, FEATURES, HIDDEN};
+
+    fn random_network() -> &'static Network {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut random = |count: usize| (0..count).map(|_| rng.gen_range(-64..64)).collect::<Vec<i16>>();
+        Box::leak(Box::new(Network {
+            feature_weights: random(FEATURES * HIDDEN),
+            feature_bias: random(HIDDEN),
+            output_weights: random(2 * HIDDEN),
+            output_bias: 10,
+        }))
+    }
+
+    #[test]
+    fn test_incremental_updates_match_refresh() {
+        let network = random_network();
+        let mut state = GameState::new_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        state.accumulator.refresh_all(Some(network), &state.piece_boards);
+        let original = state.accumulator.clone();
+        // Castling, a capture, king moves across buckets and the halves of the board, then everything undone.
+        let moves = ["e1g1", "h3g2", "g1g2", "e8c8", "g2h3", "c8b8"];
+        for text_move in moves {
+            let r#move = Move::from_text_move(&state, text_move);
+            state.apply_legal_move(r#move);
+            let mut refreshed = Accumulator::default();
+            refreshed.refresh_all(Some(network), &state.piece_boards);
+            assert!(state.accumulator.values == refreshed.values, "accumulator differs after {text_move}");
+        }
+        for _ in moves {
+            state.undo_move();
+        }
+        assert!(state.accumulator.values == original.values);
+    }
+
+    #[test]
+    fn test_simd_matches_scalar() {
+        let network = random_network();
+        let state = GameState::new_from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR w KQkq - 4 4");
+        let mut accumulator = Accumulator::default();
+        accumulator.refresh_all(Some(network), &state.piece_boards);
+        for values in &accumulator.values {
+            assert_eq!(super::crelu_dot(values, &network.output_weights), crelu_dot_scalar(values, &network.output_weights));
+        }
+    }
+
+    #[test]
+    fn test_extreme_weights_wrap() {
+        let network: &'static Network = Box::leak(Box::new(Network {
+            feature_weights: vec![i16::MAX; FEATURES * HIDDEN],
+            feature_bias: vec![i16::MAX; HIDDEN],
+            output_weights: vec![i16::MIN; 2 * HIDDEN],
+            output_bias: i16::MIN,
+        }));
+        let mut state = GameState::new_from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR w KQkq - 4 4");
+        state.accumulator.refresh_all(Some(network), &state.piece_boards);
+        let original = state.accumulator.clone();
+        state.apply_legal_move(Move::from_text_move(&state, "f3f7"));
+        network.evaluate(&state.accumulator, state.side_to_move());
+        state.undo_move();
+        assert!(state.accumulator.values == original.values);
+    }
+
+    #[test]
+    fn test_network_round_trip() {
+        let network = random_network();
+        let bytes = network.to_bytes();
+        assert!(Network::from_bytes(&bytes).unwrap() == *network);
+        assert!(Network::from_bytes(&bytes[1..]).is_err());
+    }
+}
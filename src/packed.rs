@@ -0,0 +1,322 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::bitboard::{Bitboard, Square};
+use crate::gamestate::{piece_to_char, GameState, Side, BLACK, NUM_OF_PIECES, WHITE};
+use crate::r#move::Move;
+use crate::smpsearch::Eval;
+
+/// Game results from white's point of view.
+pub const BLACK_WIN: u8 = 0;
+pub const DRAW: u8 = 1;
+pub const WHITE_WIN: u8 = 2;
+
+const NO_EN_PASSANT: u8 = 64;
+
+/// A training position in 32 bytes: the occupancy, one nibble per piece in square order and the game data.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PackedPosition {
+    occupancy: u64,
+    pieces: [u8; 16],
+    /// Side to move in the lowest bit, the castling rights above it.
+    flags: u8,
+    en_passant: u8,
+    fifty_move_rule: u8,
+    /// Search score from white's point of view.
+    pub score: i16,
+    pub result: u8,
+    pub ply: u16,
+}
+
+impl PackedPosition {
+    pub const SIZE: usize = 32;
+
+    pub fn new(state: &GameState, score: Eval, result: u8, ply: u16) -> Self {
+        let mut occupancy = Bitboard::empty();
+        for boards in &state.piece_boards {
+            for &board in boards {
+                occupancy |= board;
+            }
+        }
+        let mut pieces = [0; 16];
+        for (index, square) in occupancy.into_iter().enumerate() {
+            let (side, piece) = state.find_piece_on_all(square).unwrap();
+            pieces[index / 2] |= ((side * NUM_OF_PIECES + piece) as u8) << (4 * (index % 2));
+        }
+        let mut flags = state.side_to_move() as u8;
+        for (right, &has_right) in state.castling_rights.iter().enumerate() {
+            flags |= (has_right as u8) << (right + 1);
+        }
+        PackedPosition {
+            occupancy: occupancy.0,
+            pieces,
+            flags,
+            en_passant: if state.en_passant_board.is_filled() { state.en_passant_board.next_piece_index() as u8 } else { NO_EN_PASSANT },
+            fifty_move_rule: state.fifty_move_rule.min(u8::MAX as _) as u8,
+            score: score.clamp(i16::MIN as Eval, i16::MAX as Eval) as i16,
+            result,
+            ply,
+        }
+    }
+
+    pub fn side_to_move(&self) -> Side {
+        (self.flags & 1) as Side
+    }
+
+    /// Side and piece on every occupied square.
+    pub fn pieces(&self) -> impl Iterator<Item = (Square, Side, usize)> + '_ {
+        Bitboard(self.occupancy).into_iter().enumerate().map(|(index, square)| {
+            let nibble = (self.pieces[index / 2] >> (4 * (index % 2))) as usize & 0xF;
+            (square, nibble / NUM_OF_PIECES, nibble % NUM_OF_PIECES)
+        })
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut board = [None; 64];
+        for (square, side, piece) in self.pieces() {
+            board[square] = Some(piece_to_char(side, piece));
+        }
+        let mut fen = String::new();
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                match board[rank * 8 + file] {
+                    Some(character) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        fen.push(character);
+                    },
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if rank > 0 {
+                fen.push('/');
+            }
+        }
+        fen.push_str(if self.side_to_move() == WHITE { " w " } else { " b " });
+        // Castling rights in the order of the fen, then the en passant square and the clocks.
+        let castling: String = [(1, 'K'), (0, 'Q'), (3, 'k'), (2, 'q')].iter()
+            .filter(|&&(right, _)| self.flags & (1 << (right + 1)) != 0)
+            .map(|&(_, character)| character)
+            .collect();
+        fen.push_str(if castling.is_empty() { "-" } else { &castling });
+        let en_passant = if self.en_passant == NO_EN_PASSANT { "-".to_string() } else { Move::square_to_algebraic(self.en_passant as Square) };
+        fen.push_str(&format!(" {} {} {}", en_passant, self.fifty_move_rule, self.ply / 2 + 1));
+        fen
+    }
+
+    pub fn to_state(&self) -> GameState {
+        GameState::new_from_fen(&self.to_fen())
+    }
+
+    /// Result of the game for `side` between 0 and 1.
+    pub fn result_for(&self, side: Side) -> f64 {
+        let white = self.result as f64 / 2.0;
+        if side == BLACK { 1.0 - white } else { white }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..8].copy_from_slice(&self.occupancy.to_le_bytes());
+        bytes[8..24].copy_from_slice(&self.pieces);
+        bytes[24] = self.flags;
+        bytes[25] = self.en_passant;
+        bytes[26] = self.fifty_move_rule;
+        bytes[27..29].copy_from_slice(&self.score.to_le_bytes());
+        bytes[29] = self.result;
+        bytes[30..32].copy_from_slice(&self.ply.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        PackedPosition {
+            occupancy: u64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+            pieces: bytes[8..24].try_into().unwrap(),
+            flags: bytes[24],
+            en_passant: bytes[25],
+            fifty_move_rule: bytes[26],
+            score: i16::from_le_bytes([bytes[27], bytes[28]]),
+            result: bytes[29],
+            ply: u16::from_le_bytes([bytes[30], bytes[31]]),
+        }
+    }
+}
+
+/// Streams the positions of a packed file.
+pub struct PackedReader {
+    reader: BufReader<File>,
+}
+
+impl PackedReader {
+    pub fn open(path: &str) -> io::Result<Self> {
+        Ok(PackedReader { reader: BufReader::new(File::open(path)?) })
+    }
+}
+
+impl Iterator for PackedReader {
+    type Item = io::Result<PackedPosition>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut bytes = [0; PackedPosition::SIZE];
+        match self.reader.read_exact(&mut bytes) {
+            Ok(()) => Some(Ok(PackedPosition::from_bytes(&bytes))),
+            // A record cut short by an interrupted writer ends the file as well.
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => None,
+            Err(error) => Some(Err(error)),
+        }
+    }
+}
+
+pub struct PackedWriter {
+    writer: BufWriter<File>,
+}
+
+impl PackedWriter {
+    pub fn create(path: &str) -> io::Result<Self> {
+        Ok(PackedWriter { writer: BufWriter::new(File::create(path)?) })
+    }
+
+    /// Continues an existing file after its last complete position, or creates it. A record cut short by an
+    /// interrupted writer is dropped, everything appended after it would be misaligned.
+    pub fn append(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let length = file.metadata()?.len();
+        file.set_len(length - length % PackedPosition::SIZE as u64)?;
+        Ok(PackedWriter { writer: BufWriter::new(file) })
+    }
+
+    pub fn write(&mut self, position: &PackedPosition) -> io::Result<()> {
+        self.writer.write_all(&position.to_bytes())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Number of positions in a packed file.
+pub fn count_positions(path: &str) -> io::Result<usize> {
+    Ok(fs::metadata(path)?.len() as usize / PackedPosition::SIZE)
+}
+
+/// Shuffles `input` into `output` holding at most `memory_positions` positions at once.
+/// Larger files are scattered over temporary buckets next to `output`, which are shuffled one by one. The result is
+/// renamed to `output` once complete, so `input` may be `output`.
+pub fn shuffle_file(input: &str, output: &str, memory_positions: usize, seed: u64) -> io::Result<()> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let buckets = count_positions(input)?.div_ceil(memory_positions.max(1)).max(1);
+    let bucket_paths: Vec<String> = (0..buckets).map(|bucket| format!("{output}.bucket{bucket}")).collect();
+    if buckets > 1 {
+        let mut writers = bucket_paths.iter().map(|path| PackedWriter::create(path)).collect::<io::Result<Vec<_>>>()?;
+        for position in PackedReader::open(input)? {
+            writers[rng.gen_range(0..buckets)].write(&position?)?;
+        }
+        for writer in &mut writers {
+            writer.flush()?;
+        }
+    }
+
+    let shuffled = format!("{output}.shuffled");
+    let mut writer = PackedWriter::create(&shuffled)?;
+    for path in &bucket_paths {
+        let source = if buckets > 1 { path } else { input };
+        let mut positions = PackedReader::open(source)?.collect::<io::Result<Vec<_>>>()?;
+        positions.shuffle(&mut rng);
+        for position in &positions {
+            writer.write(position)?;
+        }
+        if buckets > 1 {
+            fs::remove_file(path)?;
+        }
+    }
+    writer.flush()?;
+    drop(writer);
+    fs::rename(shuffled, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use crate::gamestate::GameState;
+
+    use super::{shuffle_file, PackedPosition, PackedReader, PackedWriter, DRAW, WHITE_WIN};
+
+    /// A temporary file no other test or concurrent test run writes to.
+    fn temp_path(name: &str) -> String {
+        env::temp_dir().join(format!("rengine_{name}_{}.bin", process::id())).to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_packed_position_round_trip() {
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w Kq f6 0 3",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 12 40",
+        ] {
+            let state = GameState::new_from_fen(fen);
+            let ply = fen.rsplit(' ').next().unwrap().parse::<u16>().unwrap() * 2 - 2 + state.side_to_move() as u16;
+            let packed = PackedPosition::new(&state, -35, WHITE_WIN, ply);
+            assert_eq!(packed.to_fen(), fen);
+            assert!(PackedPosition::from_bytes(&packed.to_bytes()) == packed);
+            assert!(packed.to_state().zobrist == state.zobrist);
+        }
+    }
+
+    #[test]
+    fn test_append_drops_a_partial_record() {
+        let path = temp_path("append");
+        let state = GameState::new_starting_pos();
+        let mut writer = PackedWriter::create(&path).unwrap();
+        for ply in 0..3 {
+            writer.write(&PackedPosition::new(&state, 0, DRAW, ply)).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(3 * PackedPosition::SIZE as u64 - 10).unwrap();
+        drop(file);
+
+        let mut writer = PackedWriter::append(&path).unwrap();
+        writer.write(&PackedPosition::new(&state, 0, DRAW, 7)).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+        let plies: Vec<u16> = PackedReader::open(&path).unwrap().map(|position| position.unwrap().ply).collect();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(plies, vec![0, 1, 7]);
+    }
+
+    #[test]
+    fn test_shuffle_keeps_every_position() {
+        let input = temp_path("shuffle_input");
+        let output = temp_path("shuffle_output");
+        let state = GameState::new_starting_pos();
+        let mut writer = PackedWriter::create(&input).unwrap();
+        for ply in 0..100 {
+            writer.write(&PackedPosition::new(&state, 0, DRAW, ply)).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+
+        // Small enough to go through several buckets.
+        shuffle_file(&input, &output, 30, 1).unwrap();
+        let mut plies: Vec<u16> = PackedReader::open(&output).unwrap().map(|position| position.unwrap().ply).collect();
+        assert_ne!(plies, (0..100).collect::<Vec<u16>>());
+        plies.sort();
+        assert_eq!(plies, (0..100).collect::<Vec<u16>>());
+
+        // In place, within a single bucket.
+        shuffle_file(&output, &output, 1000, 2).unwrap();
+        let count = PackedReader::open(&output).unwrap().count();
+        fs::remove_file(&input).unwrap();
+        fs::remove_file(&output).unwrap();
+        assert_eq!(count, 100);
+    }
+}
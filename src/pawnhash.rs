@@ -0,0 +1,82 @@
+use std::cell::RefCell;
+use std::mem::size_of;
+use std::sync::Mutex;
+
+use crate::bitboard::Bitboard;
+use crate::gamestate::NUM_OF_PLAYERS;
+use crate::smpsearch::Eval;
+use crate::zobrist::ZobristHash;
+
+pub const DEFAULT_PAWN_HASH_SIZE: usize = 1;
+pub const MAX_PAWN_HASH_SIZE: usize = 256;
+
+/// Size of every thread's pawn hash table in megabytes, set by the `PawnHash` option.
+pub static mut PAWN_HASH_SIZE: usize = DEFAULT_PAWN_HASH_SIZE;
+
+/// Tables of finished search threads. Searches spawn their threads anew, the tables outlive them here
+/// so the next search doesn't start cold.
+static SPARE_TABLES: Mutex<Vec<PawnHashTable>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static PAWN_HASH: RefCell<Option<PawnHashTable>> = const { RefCell::new(None) };
+}
+
+/// Pawn structure evaluation of a pawn configuration, it depends on nothing else.
+#[derive(Copy, Clone, Default)]
+pub struct PawnEntry {
+    key: ZobristHash,
+    pub mg: [Eval; NUM_OF_PLAYERS],
+    pub eg: [Eval; NUM_OF_PLAYERS],
+    pub passed: [Bitboard; NUM_OF_PLAYERS],
+}
+
+impl PawnEntry {
+    pub fn new(key: ZobristHash, mg: [Eval; NUM_OF_PLAYERS], eg: [Eval; NUM_OF_PLAYERS], passed: [Bitboard; NUM_OF_PLAYERS]) -> Self {
+        PawnEntry { key, mg, eg, passed }
+    }
+}
+
+pub struct PawnHashTable {
+    megabytes: usize,
+    entries: Vec<PawnEntry>,
+}
+
+impl PawnHashTable {
+    pub fn new(megabytes: usize) -> Self {
+        let length = (megabytes << 20) / size_of::<PawnEntry>();
+        PawnHashTable { megabytes, entries: vec![PawnEntry::default(); length.max(1)] }
+    }
+
+    /// The cached entry of `key`, computed by `evaluate` and stored on a miss.
+    pub fn probe(&mut self, key: ZobristHash, evaluate: impl FnOnce() -> PawnEntry) -> PawnEntry {
+        let index = key.0 as usize % self.entries.len();
+        if self.entries[index].key != key {
+            self.entries[index] = evaluate();
+        }
+        self.entries[index]
+    }
+}
+
+/// Looks up the pawn structure of `key` in the calling thread's table, threads outside a search get their own.
+pub fn probe_pawn_hash(key: ZobristHash, evaluate: impl FnOnce() -> PawnEntry) -> PawnEntry {
+    PAWN_HASH.with_borrow_mut(|table| {
+        table.get_or_insert_with(|| PawnHashTable::new(unsafe { PAWN_HASH_SIZE })).probe(key, evaluate)
+    })
+}
+
+/// Runs a search thread's `search` with a spare table of the current `PawnHash` size and keeps the table for
+/// the next search. Spares of an outdated size are dropped.
+pub fn with_spare_pawn_hash<R>(search: impl FnOnce() -> R) -> R {
+    let megabytes = unsafe { PAWN_HASH_SIZE };
+    let spare = {
+        let mut spare_tables = SPARE_TABLES.lock().unwrap();
+        spare_tables.retain(|table| table.megabytes == megabytes);
+        spare_tables.pop()
+    };
+    PAWN_HASH.set(Some(spare.unwrap_or_else(|| PawnHashTable::new(megabytes))));
+    let result = search();
+    if let Some(table) = PAWN_HASH.take() {
+        SPARE_TABLES.lock().unwrap().push(table);
+    }
+    result
+}
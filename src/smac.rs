@@ -1,7 +1,6 @@
-use std::{fs, io::Write, env};
 use clap::Parser;
 
-use crate::{smpsearch::Eval, eval::{EvalParams, EVAL_PARAMS}, gamestate::{PAWN, ROOK, KNIGHT, BISHOP, QUEEN}, texel::{read_texel_sample_file, mean_square_error, K}};
+use crate::{smpsearch::Eval, eval::{EvalParams, EVAL_PARAMS}, gamestate::{PAWN, ROOK, KNIGHT, BISHOP, QUEEN}, texel::{read_texel_sample_file, mean_square_error, TexelPositions, K}};
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -45,29 +44,30 @@ struct Args {
 pub fn smac() -> std::io::Result<()> {
     let args = Args::parse();
 
-    unsafe {
-        EVAL_PARAMS.mg_piece_value[PAWN] = args.mp;
-        EVAL_PARAMS.mg_piece_value[ROOK] = args.mr;
-        EVAL_PARAMS.mg_piece_value[KNIGHT] = args.mn;
-        EVAL_PARAMS.mg_piece_value[BISHOP] = args.mb;
-        EVAL_PARAMS.mg_piece_value[QUEEN] = args.mq;
-        
-        EVAL_PARAMS.eg_piece_value[PAWN] = args.ep;
-        EVAL_PARAMS.eg_piece_value[ROOK] = args.er;
-        EVAL_PARAMS.eg_piece_value[KNIGHT] = args.en;
-        EVAL_PARAMS.eg_piece_value[BISHOP] = args.eb;
-        EVAL_PARAMS.eg_piece_value[QUEEN] = args.eq;
+    // A copy, the engine's own params stay untouched.
+    let mut params: EvalParams = unsafe { EVAL_PARAMS.clone() };
+    params.mg_piece_value[PAWN] = args.mp;
+    params.mg_piece_value[ROOK] = args.mr;
+    params.mg_piece_value[KNIGHT] = args.mn;
+    params.mg_piece_value[BISHOP] = args.mb;
+    params.mg_piece_value[QUEEN] = args.mq;
+    
+    params.eg_piece_value[PAWN] = args.ep;
+    params.eg_piece_value[ROOK] = args.er;
+    params.eg_piece_value[KNIGHT] = args.en;
+    params.eg_piece_value[BISHOP] = args.eb;
+    params.eg_piece_value[QUEEN] = args.eq;
+
+    params.mg_doubled_penalty = args.mdp;
+    params.eg_doubled_penalty = args.edp;
+    params.mg_isolated_penalty = args.mip;
+    params.eg_isolated_penalty = args.eip;
+    params.mg_supported_bonus = args.msb;
+    params.eg_supported_bonus = args.esb;
 
-        EVAL_PARAMS.mg_doubled_penalty = args.mdp;
-        EVAL_PARAMS.eg_doubled_penalty = args.edp;
-        EVAL_PARAMS.mg_isolated_penalty = args.mip;
-        EVAL_PARAMS.eg_isolated_penalty = args.eip;
-        EVAL_PARAMS.mg_supported_bonus = args.msb;
-        EVAL_PARAMS.eg_supported_bonus = args.esb;
-        
-    }
-    let fen_and_values = read_texel_sample_file();
-    let mut best_e = mean_square_error(K, &fen_and_values);
-    println!("cost={}", best_e);
+    // Every run scores a single set of params, so resolving the leaves first would gain nothing.
+    let positions = TexelPositions::load(&read_texel_sample_file(), false);
+    let error = mean_square_error(K, &positions, &params);
+    println!("cost={}", error);
     Ok(())
 }
\ No newline at end of file
@@ -1,27 +1,157 @@
-use std::{cell::SyncUnsafeCell, sync::Arc, time::{Duration, Instant}, marker::ConstParamTy, thread};
+use std::{cell::SyncUnsafeCell, sync::Arc, time::{Duration, Instant}, marker::ConstParamTy, thread, cmp::{min, max}};
 
 use rand::{distributions::WeightedIndex, thread_rng};
 
-use crate::{r#move::{Move, MoveList}, bitboard::NUM_OF_SQUARES, gamestate::{GameState, NUM_OF_PLAYERS, Side, NUM_OF_PIECES}, lockless::{LockLessTransTable, LockLessValue, LockLessFlag}, book::OPENING_BOOK};
+use crate::{r#move::{Move, MoveList}, timeman::TimeManager, bitboard::NUM_OF_SQUARES, gamestate::{GameState, NUM_OF_PLAYERS, Side, Piece, NUM_OF_PIECES}, lockless::{LockLessTransTable, LockLessFlag}, book::OPENING_BOOK, eval::EVAL_PARAMS, nnue, pawnhash};
 
 use rand::prelude::*;
 
-const MAX_SEARCH_DEPTH: u8 = 30;
+/// Deepest search ply the search stack can hold.
+const MAX_PLY: usize = 128;
+const LMR_TABLE_SIZE: usize = 64;
+/// Bound of every history entry, kept by the gravity update.
+const HISTORY_MAX: i32 = 16384;
+/// Number of earlier plies whose moves index a continuation history.
+const CONTINUATION_PLIES: usize = 2;
 const MAX_KILLER_MOVES: usize = 2;
-const MAX_QUIESCENT_DEPTH: u8 = 10;
-const MAX_CHECK_EXTENSIONS: u8 = 8;
+/// Number of nodes between two polls of the clock and the stop flag.
+const STOP_CHECK_INTERVAL: u64 = 2048;
+/// Safety margin on top of the captured piece's value before a capture is delta pruned.
+const DELTA_MARGIN: Eval = 200;
 pub type Eval = i32;
+/// Remaining search depth in plies, signed so reductions and extensions can't underflow.
+pub type Depth = i32;
 
 pub const NULLMOVE: Move = Move(0);
 
 pub const INFINITY: Eval = 30_000;
 pub const AB_BOUND: Eval = INFINITY - 3000;
-pub const ISMATE: Eval = AB_BOUND - MAX_DEPTH as Eval;
+/// Every score beyond this is a mate, found at most `MAX_PLY` plies from the root.
+pub const ISMATE: Eval = AB_BOUND - MAX_PLY as Eval;
 pub const MAX_DEPTH: usize = 50;
 
+/// Score of mating the opponent `ply` plies from the root.
+pub fn mate_in(ply: usize) -> Eval {
+    AB_BOUND - ply as Eval
+}
+
+/// Score of getting mated `ply` plies from the root.
+pub fn mated_in(ply: usize) -> Eval {
+    -AB_BOUND + ply as Eval
+}
+
+/// The transposition table stores mate scores relative to the node instead of the root,
+/// so they stay correct when the position is reached at another ply.
+pub fn score_to_tt(score: Eval, ply: usize) -> Eval {
+    if score > ISMATE {
+        score + ply as Eval
+    } else if score < -ISMATE {
+        score - ply as Eval
+    } else {
+        score
+    }
+}
+
+/// Reverses `score_to_tt` for a node `ply` plies from the root.
+pub fn score_from_tt(score: Eval, ply: usize) -> Eval {
+    if score > ISMATE {
+        score - ply as Eval
+    } else if score < -ISMATE {
+        score + ply as Eval
+    } else {
+        score
+    }
+}
+
+/// Full moves until mate for UCI's `score mate`, negative when the side to move gets mated.
+pub fn moves_to_mate(score: Eval) -> Eval {
+    if score > 0 {
+        (AB_BOUND - score + 1) / 2
+    } else {
+        -(AB_BOUND + score) / 2
+    }
+}
+
+/// Margins and depth limits of the forward pruning in `alpha_beta`, and the draw contempt.
+pub struct SearchParams {
+    pub rfp_max_depth: Depth,
+    pub rfp_margin: Eval,
+    pub razor_max_depth: Depth,
+    pub razor_margin: Eval,
+    pub futility_max_depth: Depth,
+    pub futility_base: Eval,
+    pub futility_margin: Eval,
+    pub lmp_max_depth: Depth,
+    pub iir_min_depth: Depth,
+    pub lmp_base: u32,
+    pub lmr_min_depth: Depth,
+    pub lmr_base: f64,
+    pub lmr_divisor: f64,
+    pub lmr_history_divisor: i32,
+    pub history_bonus_scale: i32,
+    pub history_bonus_max: i32,
+    pub nmp_min_depth: Depth,
+    pub nmp_base_reduction: Depth,
+    pub nmp_depth_divisor: Depth,
+    pub nmp_eval_divisor: Eval,
+    pub nmp_max_eval_reduction: Depth,
+    pub se_min_depth: Depth,
+    pub se_tt_depth_margin: Depth,
+    pub se_margin: Eval,
+    pub se_double_margin: Eval,
+    /// Penalty of a draw for the side to move at the root.
+    pub contempt: Eval,
+    /// Fades the contempt out as material comes off the board.
+    pub dynamic_contempt: bool,
+}
+
+pub static mut SEARCH_PARAMS: SearchParams = SearchParams {
+    rfp_max_depth: 8,
+    rfp_margin: 80,
+    razor_max_depth: 3,
+    razor_margin: 250,
+    futility_max_depth: 6,
+    futility_base: 100,
+    futility_margin: 90,
+    lmp_max_depth: 6,
+    lmp_base: 3,
+    iir_min_depth: 4,
+    lmr_min_depth: 3,
+    lmr_base: 0.75,
+    lmr_divisor: 2.25,
+    lmr_history_divisor: 8192,
+    history_bonus_scale: 32,
+    history_bonus_max: 1600,
+    nmp_min_depth: 3,
+    nmp_base_reduction: 3,
+    nmp_depth_divisor: 3,
+    nmp_eval_divisor: 200,
+    nmp_max_eval_reduction: 3,
+    se_min_depth: 8,
+    se_tt_depth_margin: 3,
+    se_margin: 2,
+    se_double_margin: 20,
+    contempt: 0,
+    dynamic_contempt: true,
+};
+
+/// Everything besides the stop flag that ends a search.
+#[derive(Clone, Copy)]
+pub struct SearchLimits {
+    pub time_manager: TimeManager,
+    /// Exclusive bound of the iterative deepening depth.
+    pub max_depth: u8,
+    pub max_nodes: u64,
+}
+
+impl SearchLimits {
+    pub fn new(time_manager: TimeManager, max_depth: u8) -> Self {
+        SearchLimits { time_manager, max_depth, max_nodes: u64::MAX }
+    }
+}
+
 pub struct ThreadData {
     state: GameState,
-    max_depth: u8,
     trans_table: Arc<SyncUnsafeCell<LockLessTransTable>>,
     thread_num: usize,
     search_info: SearchInfo,
@@ -29,55 +159,254 @@ pub struct ThreadData {
 
 impl ThreadData {
     pub fn clear_for_search(&mut self) {
-        self.search_info.clear_for_search();
         self.state.search_ply = 0;
     }
+
+    /// One iteration of iterative deepening, check `search_info.stopped` before using the score.
+    fn search_root<const SEARCHMODE: SearchProtocol>(&mut self, depth: u8) -> Eval {
+        self.search_info.search_depth = depth;
+        self.search_info.root_best = None;
+        alpha_beta::<SEARCHMODE>(-AB_BOUND, AB_BOUND, &mut self.state, depth as Depth, &mut self.search_info, self.trans_table.get(), true, false)
+    }
 }
 
 pub struct SearchInfo {
     start_time: Instant,
-    max_time: Duration,
+    limits: SearchLimits,
     killer_table: KillerTable,
-    history_table: [[[u32; NUM_OF_SQUARES]; NUM_OF_SQUARES]; NUM_OF_PLAYERS],
+    history_table: [[[i32; NUM_OF_SQUARES]; NUM_OF_SQUARES]; NUM_OF_PLAYERS],
+    counter_moves: [[[Move; NUM_OF_SQUARES]; NUM_OF_PIECES]; NUM_OF_PLAYERS],
+    continuation_history: [ContinuationHistory; CONTINUATION_PLIES],
+    capture_history: [[[[i32; NUM_OF_PIECES]; NUM_OF_SQUARES]; NUM_OF_PIECES]; NUM_OF_PLAYERS],
     stop_flag: Arc<SyncUnsafeCell<bool>>,
+    /// Set once a limit or the stop flag ended the search, every score searched afterwards is garbage.
+    stopped: bool,
+    contempt: Eval,
+    /// Best root move of the running iteration, only set by moves that were fully searched and raised alpha.
+    root_best: Option<(Move, Eval)>,
     search_depth: u8,
     nodes_searched: u64,
+    stack: [StackEntry; MAX_PLY],
+    pv_table: PvTable,
+    lmr_table: [[u8; LMR_TABLE_SIZE]; LMR_TABLE_SIZE],
 }
 
 impl SearchInfo {
-    pub fn new(max_time: Duration, stop_flag: Arc<SyncUnsafeCell<bool>>) -> Self {
-        SearchInfo { start_time: Instant::now(), max_time, killer_table: Default::default(), history_table: [[[0; NUM_OF_SQUARES]; NUM_OF_SQUARES]; NUM_OF_PLAYERS], stop_flag, search_depth: 0, nodes_searched: 0 }
+    pub fn new(limits: SearchLimits, stop_flag: Arc<SyncUnsafeCell<bool>>) -> Self {
+        SearchInfo {
+            start_time: Instant::now(),
+            limits,
+            killer_table: KillerTable([[NULLMOVE; MAX_KILLER_MOVES]; MAX_PLY]),
+            history_table: [[[0; NUM_OF_SQUARES]; NUM_OF_SQUARES]; NUM_OF_PLAYERS],
+            counter_moves: [[[NULLMOVE; NUM_OF_SQUARES]; NUM_OF_PIECES]; NUM_OF_PLAYERS],
+            continuation_history: [ContinuationHistory::new(), ContinuationHistory::new()],
+            capture_history: [[[[0; NUM_OF_PIECES]; NUM_OF_SQUARES]; NUM_OF_PIECES]; NUM_OF_PLAYERS],
+            stop_flag,
+            stopped: false,
+            contempt: 0,
+            root_best: None,
+            search_depth: 0,
+            nodes_searched: 0,
+            stack: [StackEntry::default(); MAX_PLY],
+            pv_table: PvTable { moves: [[NULLMOVE; MAX_PLY]; MAX_PLY], length: [0; MAX_PLY] },
+            lmr_table: lmr_table(),
+        }
     }
 
-    fn time_over(&self) -> bool {
-        self.start_time.elapsed() > self.max_time
+    /// Polled at every node. The clock and the stop flag are only read every `STOP_CHECK_INTERVAL` nodes.
+    /// The clock spares the first iteration so there is a searched move to play, the node limit and the flag don't.
+    fn should_stop<const SEARCHMODE: SearchProtocol>(&mut self) -> bool {
+        if self.stopped {
+            return true;
+        }
+        if self.nodes_searched >= self.limits.max_nodes {
+            self.stopped = true;
+        } else if self.nodes_searched % STOP_CHECK_INTERVAL == 0 {
+            let stop_flag = unsafe { *self.stop_flag.get() };
+            let out_of_time = self.search_depth > 1 && self.limits.time_manager.hard_limit_reached();
+            self.stopped = match SEARCHMODE {
+                // A ponder search runs for as long as the flag is raised.
+                SearchProtocol::Uci(UciMode::Ponder) => !stop_flag,
+                SearchProtocol::Uci(UciMode::Movetime) | SearchProtocol::Texel => stop_flag || out_of_time,
+                SearchProtocol::Uci(UciMode::Infinite) | SearchProtocol::Debug => stop_flag,
+            };
+        }
+        self.stopped
     }
 
-    pub fn clear_for_search(&mut self) {
-        self.nodes_searched = 0;
+    /// The root move an interrupted iteration plays instead of the previous iteration's best.
+    /// The first root move searched always raises alpha and helper threads may have replaced the
+    /// root hash move, so only a fully searched move that beat the previous best score is trusted.
+    fn interrupted_root_move(&self, best_move: Move, best_eval: Eval) -> Option<(Move, Eval)> {
+        self.root_best.filter(|&(root_move, root_score)| root_move != best_move && root_score > best_eval)
     }
 
     pub fn nps(&self) -> u64 {
         self.nodes_searched.checked_div(self.start_time.elapsed().as_millis() as u64).unwrap_or(0) * 1000
     }
+
+    /// Score of a draw for the side to move at `ply`. The root side gives up the contempt and the node
+    /// count adds a jitter of one centipawn, so not every draw line looks alike.
+    fn draw_score(&self, ply: usize) -> Eval {
+        let contempt = if ply % 2 == 0 { -self.contempt } else { self.contempt };
+        contempt + (self.nodes_searched & 2) as Eval - 1
+    }
+
+    /// The stack entry of the move played `plies_ago` plies before `ply`, if it was a real move.
+    fn previous_move(&self, ply: usize, plies_ago: usize) -> Option<StackEntry> {
+        if ply < plies_ago || self.stack[ply - plies_ago].r#move == NULLMOVE {
+            return None;
+        }
+        Some(self.stack[ply - plies_ago])
+    }
+
+    fn counter_move(&self, ply: usize, side: Side) -> Move {
+        match self.previous_move(ply, 1) {
+            Some(previous) => self.counter_moves[side][previous.piece][previous.r#move.to()],
+            None => NULLMOVE,
+        }
+    }
+
+    /// Butterfly history plus the continuation histories of a quiet move.
+    fn quiet_history(&self, ply: usize, side: Side, r#move: Move) -> i32 {
+        let mut score = self.history_table[side][r#move.from()][r#move.to()];
+        for (offset, table) in self.continuation_history.iter().enumerate() {
+            if let Some(previous) = self.previous_move(ply, offset + 1) {
+                score += table.0[ContinuationHistory::index(side, previous, r#move)];
+            }
+        }
+        score
+    }
+
+    /// Whether `move` captures back on the square where the previous move captured.
+    fn is_recapture(&self, ply: usize, r#move: Move) -> bool {
+        r#move.is_capture() && self.previous_move(ply, 1).is_some_and(|previous| previous.r#move.is_capture() && previous.r#move.to() == r#move.to())
+    }
+
+    fn capture_history(&self, side: Side, r#move: Move) -> i32 {
+        self.capture_history[side][r#move.moving_piece()][r#move.to()][r#move.captured_piece()]
+    }
+
+    /// Rewards the quiet move that caused a beta cutoff and punishes the quiets searched before it.
+    fn update_quiet_histories(&mut self, ply: usize, side: Side, best_move: Move, quiets_tried: &MoveList, bonus: i32) {
+        self.update_quiet_history(ply, side, best_move, bonus);
+        for index in 0..quiets_tried.length {
+            self.update_quiet_history(ply, side, quiets_tried.moves[index as usize], -bonus);
+        }
+        if let Some(previous) = self.previous_move(ply, 1) {
+            self.counter_moves[side][previous.piece][previous.r#move.to()] = best_move;
+        }
+    }
+
+    fn update_quiet_history(&mut self, ply: usize, side: Side, r#move: Move, bonus: i32) {
+        apply_gravity(&mut self.history_table[side][r#move.from()][r#move.to()], bonus);
+        for offset in 0..CONTINUATION_PLIES {
+            if let Some(previous) = self.previous_move(ply, offset + 1) {
+                apply_gravity(&mut self.continuation_history[offset].0[ContinuationHistory::index(side, previous, r#move)], bonus);
+            }
+        }
+    }
+
+    /// Rewards a capture that caused a beta cutoff and punishes the captures searched before the cutoff.
+    fn update_capture_histories(&mut self, side: Side, best_move: Move, captures_tried: &MoveList, bonus: i32) {
+        if best_move.is_capture() {
+            apply_gravity(&mut self.capture_history[side][best_move.moving_piece()][best_move.to()][best_move.captured_piece()], bonus);
+        }
+        for index in 0..captures_tried.length {
+            let r#move = captures_tried.moves[index as usize];
+            apply_gravity(&mut self.capture_history[side][r#move.moving_piece()][r#move.to()][r#move.captured_piece()], -bonus);
+        }
+    }
 }
 
-#[derive(Default)]
-struct KillerTable([[Move; MAX_KILLER_MOVES]; MAX_SEARCH_DEPTH as usize]);
+/// Per ply information of the current search path, indexed by `GameState::search_ply`.
+#[derive(Default, Clone, Copy)]
+struct StackEntry {
+    static_eval: Eval,
+    r#move: Move,
+    piece: Piece,
+    /// Move skipped by the singular extension search of this node.
+    excluded_move: Move,
+}
+
+/// Triangular table of principal variations, `moves[ply]` holds the line from `ply` up to `length[ply]`.
+struct PvTable {
+    moves: [[Move; MAX_PLY]; MAX_PLY],
+    length: [usize; MAX_PLY],
+}
+
+impl PvTable {
+    fn clear(&mut self, ply: usize) {
+        self.length[ply] = ply;
+    }
+
+    /// Makes `move` followed by the line of the child node the new variation of `ply`.
+    fn update(&mut self, ply: usize, r#move: Move) {
+        self.moves[ply][ply] = r#move;
+        let child_length = if ply + 1 < MAX_PLY { self.length[ply + 1] } else { ply + 1 };
+        for index in ply + 1..child_length {
+            self.moves[ply][index] = self.moves[ply + 1][index];
+        }
+        self.length[ply] = child_length.max(ply + 1);
+    }
+
+    fn line(&self) -> &[Move] {
+        &self.moves[0][..self.length[0]]
+    }
+}
+
+/// History of a move indexed by the piece and target square of an earlier move on the search path.
+struct ContinuationHistory(Vec<i32>);
+
+impl ContinuationHistory {
+    fn new() -> Self {
+        ContinuationHistory(vec![0; NUM_OF_PLAYERS * NUM_OF_PIECES * NUM_OF_SQUARES * NUM_OF_PIECES * NUM_OF_SQUARES])
+    }
+
+    fn index(side: Side, previous: StackEntry, r#move: Move) -> usize {
+        (((side * NUM_OF_PIECES + previous.piece) * NUM_OF_SQUARES + previous.r#move.to()) * NUM_OF_PIECES + r#move.moving_piece()) * NUM_OF_SQUARES + r#move.to()
+    }
+}
+
+/// Moves a history entry by `bonus`, damped the closer it already is to `HISTORY_MAX`.
+fn apply_gravity(entry: &mut i32, bonus: i32) {
+    let bonus = bonus.clamp(-HISTORY_MAX, HISTORY_MAX);
+    *entry += bonus - *entry * bonus.abs() / HISTORY_MAX;
+}
+
+fn history_bonus(depth: Depth) -> i32 {
+    let params = unsafe { &SEARCH_PARAMS };
+    min(params.history_bonus_scale * depth * depth, params.history_bonus_max)
+}
+
+/// Late move reductions indexed by `[depth][move_number]`, growing with the logarithm of both.
+fn lmr_table() -> [[u8; LMR_TABLE_SIZE]; LMR_TABLE_SIZE] {
+    let params = unsafe { &SEARCH_PARAMS };
+    let mut table = [[0; LMR_TABLE_SIZE]; LMR_TABLE_SIZE];
+    for depth in 1..LMR_TABLE_SIZE {
+        for move_number in 1..LMR_TABLE_SIZE {
+            let reduction = params.lmr_base + (depth as f64).ln() * (move_number as f64).ln() / params.lmr_divisor;
+            table[depth][move_number] = reduction as u8;
+        }
+    }
+    table
+}
+
+struct KillerTable([[Move; MAX_KILLER_MOVES]; MAX_PLY]);
 
 impl KillerTable {
-    fn store_killer(&mut self, depth: u8, r#move: Move) {
-        if self.0[depth as usize][0] != r#move {
-            self.0[depth as usize][1] = self.0[depth as usize][0];
-            self.0[depth as usize][0] = r#move;
+    fn store_killer(&mut self, ply: usize, r#move: Move) {
+        if self.0[ply][0] != r#move {
+            self.0[ply][1] = self.0[ply][0];
+            self.0[ply][0] = r#move;
         }
     }
 
-    fn is_killer(&mut self, depth: u8, r#move: Move) -> Option<u8> {
-        if self.0[depth as usize][0] == r#move {
+    fn is_killer(&self, ply: usize, r#move: Move) -> Option<u8> {
+        if self.0[ply][0] == r#move {
             return Some(0);
         }
-        if self.0[depth as usize][1] == r#move {
+        if self.0[ply][1] == r#move {
             return Some(1);
         }
         None
@@ -98,7 +427,7 @@ pub enum UciMode {
     Ponder,
 }
 
-pub fn search<const SEARCHMODE: SearchProtocol>(threads: usize, max_time: Duration, state: GameState, stop_flag: Arc<SyncUnsafeCell<bool>>, max_depth: u8, trans_table: Arc<SyncUnsafeCell<LockLessTransTable>>) -> (Move, Eval) {
+pub fn search<const SEARCHMODE: SearchProtocol>(threads: usize, limits: SearchLimits, state: GameState, stop_flag: Arc<SyncUnsafeCell<bool>>, trans_table: Arc<SyncUnsafeCell<LockLessTransTable>>) -> (Move, Eval) {
     if let Some(entry) = OPENING_BOOK.get(&state.to_reduced_book_fen()) {
             
         let moves: Vec<String> = entry.iter().map(|item| item.0.to_string()).collect();
@@ -119,20 +448,24 @@ pub fn search<const SEARCHMODE: SearchProtocol>(threads: usize, max_time: Durati
         return (Move::from_text_move(&state, &r#move), 0);
     }
     
+    // The position may predate the current `EvalFile`.
+    let mut state = state;
+    state.refresh_accumulator(nnue::network());
     let mut thread_pool = vec![];
     for thread in (0..threads).rev() {
         let state_clone = state.clone();
         let trans_table_clone = Arc::clone(&trans_table);
         let stop_flag_clone = Arc::clone(&stop_flag);
         thread_pool.push(thread::spawn(move || {
-            let thread_data = ThreadData {
+            let mut search_info = SearchInfo::new(limits, stop_flag_clone);
+            search_info.contempt = root_contempt(&state_clone);
+            let mut thread_data = ThreadData {
                 state: state_clone,
-                max_depth,
                 trans_table: trans_table_clone,
                 thread_num: thread,
-                search_info: SearchInfo::new(max_time, stop_flag_clone)
+                search_info,
             };
-            iterative_deepening::<SEARCHMODE>(thread_data)
+            pawnhash::with_spare_pawn_hash(|| iterative_deepening::<SEARCHMODE>(&mut thread_data))
         }));
     }
     let mut results = Vec::new();
@@ -145,30 +478,50 @@ pub fn search<const SEARCHMODE: SearchProtocol>(threads: usize, max_time: Durati
     results[0]
 }
 
-fn extract_pv(state: &GameState, t_table: *mut LockLessTransTable) -> Vec<Move> {
-    let mut moves = Vec::new();
-    let mut copy_state = state.clone();
-    unsafe {
-        let mut depth = 0;
-        while let Some(entry) = (*t_table).get(copy_state.zobrist) && !copy_state.unavoidable_game_over() {
-            if depth > entry.depth() {
-                break;
-            }
-            let pvmove = entry.best_move();
-            moves.push(pvmove);
-            copy_state.apply_legal_move(pvmove);
-            depth += 1;
-        }
+fn root_contempt(state: &GameState) -> Eval {
+    let params = unsafe { &SEARCH_PARAMS };
+    if params.dynamic_contempt {
+        params.contempt * (256 - state.phase()) / 256
+    } else {
+        params.contempt
     }
+}
 
-    if moves.is_empty() {
-        moves.push(copy_state.find_first_legal_move());
-    }
+/// Positions of `bench`, the total node count over them is a signature of the search.
+const BENCH_FENS: [&str; 8] = [
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP1QBPPP/R3KB1R w KQ - 0 9",
+    "2r3k1/pp3ppp/2n1b3/q2pP3/3P4/P1PB1N2/5PPP/R2Q1RK1 b - - 0 18",
+    "r2q1rk1/1b1nbppp/p2ppn2/1p6/3NP3/1BN1BP2/PPPQ2PP/2KR3R w - - 2 12",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "6k1/5p2/6p1/8/7p/8/6PP/6K1 b - - 0 40",
+    "4rrk1/pp1n3p/3q2pQ/2p1pb2/2PP4/2P3N1/P2B2PP/4RRK1 b - - 7 19",
+    "8/8/1p1k4/p1p2p2/P1P2P2/1P1K4/8/8 w - - 0 1",
+];
 
-    moves
+/// Searches every bench position to a fixed depth and prints the summed nodes and speed.
+pub fn bench(depth: u8) {
+    let start_time = Instant::now();
+    let trans_table = Arc::new(SyncUnsafeCell::new(LockLessTransTable::new()));
+    let mut nodes = 0;
+    for fen in BENCH_FENS {
+        unsafe {
+            (*trans_table.get()).clear();
+        }
+        let mut thread_data = ThreadData {
+            state: GameState::new_from_fen(fen),
+            trans_table: Arc::clone(&trans_table),
+            thread_num: 0,
+            search_info: SearchInfo::new(SearchLimits::new(TimeManager::fixed(Duration::MAX), depth + 1), Arc::new(SyncUnsafeCell::new(false))),
+        };
+        iterative_deepening::<{SearchProtocol::Debug}>(&mut thread_data);
+        nodes += thread_data.search_info.nodes_searched;
+    }
+    let elapsed = start_time.elapsed();
+    println!("{} nodes {} nps", nodes, (nodes as f64 / elapsed.as_secs_f64()) as u64);
 }
 
-fn pv_to_string(pv: &Vec<Move>) -> String {
+fn pv_to_string(pv: &[Move]) -> String {
     let mut string = String::new();
     for p in pv {
         string.push_str(&format!("{} ", p.to_algebraic()));
@@ -176,47 +529,58 @@ fn pv_to_string(pv: &Vec<Move>) -> String {
     string
 }
 
-pub fn iterative_deepening<const SEARCHMODE: SearchProtocol>(mut thread_data: ThreadData) -> (Move, Eval) {
+pub fn iterative_deepening<const SEARCHMODE: SearchProtocol>(thread_data: &mut ThreadData) -> (Move, Eval) {
     let mut best_move = NULLMOVE;
+    let mut ponder_move = NULLMOVE;
     let mut best_eval: i32 = -AB_BOUND;
+    // The root state was cloned from the game, whose moves were counted as search plies too.
+    thread_data.clear_for_search();
     let start_depth = if thread_data.thread_num % 2 == 1 {
         2
     } else {
         1
     };
-    for depth in start_depth..thread_data.max_depth {
-        thread_data.search_info.search_depth = depth;
-        let score = alpha_beta::<SEARCHMODE>(-AB_BOUND, AB_BOUND, &mut thread_data.state, depth, &mut thread_data.search_info, thread_data.trans_table.get(), true, 0);
-        
-        unsafe {
-            if depth > 2 && ((SEARCHMODE == SearchProtocol::Uci(UciMode::Movetime) || SEARCHMODE == SearchProtocol::Texel) && thread_data.search_info.time_over()) {
-                break;
-            }
-            if *thread_data.search_info.stop_flag.get() && SEARCHMODE != SearchProtocol::Uci(UciMode::Ponder) {
-                break;
-            }
-            if (SEARCHMODE == SearchProtocol::Uci(UciMode::Ponder)) && !(*thread_data.search_info.stop_flag.get()) {
-                break;
+    for depth in start_depth..thread_data.search_info.limits.max_depth {
+        let score = thread_data.search_root::<SEARCHMODE>(depth);
+        if thread_data.search_info.stopped {
+            if let Some((root_move, root_score)) = thread_data.search_info.interrupted_root_move(best_move, best_eval) {
+                best_move = root_move;
+                best_eval = root_score;
+                ponder_move = NULLMOVE;
             }
+            break;
         }
         best_eval = score;
-        let pv = extract_pv(&thread_data.state, thread_data.trans_table.get());
-        best_move = pv[0];
-        if let SearchProtocol::Uci(ucimode) = SEARCHMODE && thread_data.thread_num == 0 && ucimode != UciMode::Ponder {
-            let pv_string = pv_to_string(&pv);
-            if best_eval.abs() >= ISMATE {
-                let mate_in = (AB_BOUND - best_eval.abs()) / 2 + 1;
-                println!("info depth {} nodes {} nps {} score mate {} pv {}", depth, thread_data.search_info.nodes_searched, thread_data.search_info.nps(), mate_in, pv_string);
-            } else {
-                println!("info depth {} nodes {} nps {} score cp {} pv {}", depth, thread_data.search_info.nodes_searched, thread_data.search_info.nps(), best_eval, pv_string);
+        let pv = thread_data.search_info.pv_table.line();
+        // A root without a PV scored nothing above alpha, any move that was searched is as good.
+        best_move = match (pv.first(), thread_data.search_info.root_best) {
+            (Some(&r#move), _) | (None, Some((r#move, _))) => r#move,
+            (None, None) => thread_data.state.generate_legal_moves().into_iter().next().unwrap_or(NULLMOVE),
+        };
+        ponder_move = pv.get(1).copied().unwrap_or(NULLMOVE);
+        if let SearchProtocol::Uci(ucimode) = SEARCHMODE {
+            if thread_data.thread_num == 0 && ucimode != UciMode::Ponder {
+                let pv_string = pv_to_string(pv);
+                if best_eval.abs() >= ISMATE {
+                    println!("info depth {} nodes {} nps {} score mate {} pv {}", depth, thread_data.search_info.nodes_searched, thread_data.search_info.nps(), moves_to_mate(best_eval), pv_string);
+                } else {
+                    println!("info depth {} nodes {} nps {} score cp {} pv {}", depth, thread_data.search_info.nodes_searched, thread_data.search_info.nps(), best_eval, pv_string);
+                }
             }
         }
         thread_data.clear_for_search();
         if best_eval.abs() >= ISMATE {
             break;
         }
+        if (SEARCHMODE == SearchProtocol::Uci(UciMode::Movetime) || SEARCHMODE == SearchProtocol::Texel) && !thread_data.search_info.limits.time_manager.continue_iterating(best_move, best_eval) {
+            break;
+        }
 
     }
+    // Stopped before the first iteration found a move.
+    if best_move == NULLMOVE {
+        best_move = thread_data.state.generate_legal_moves().into_iter().next().unwrap_or(NULLMOVE);
+    }
 
     if thread_data.thread_num == 0 {
         unsafe {
@@ -225,7 +589,9 @@ pub fn iterative_deepening<const SEARCHMODE: SearchProtocol>(mut thread_data: Th
             }
         }
         if let SearchProtocol::Uci(ucimode) = SEARCHMODE {
-            if ucimode != UciMode::Ponder {
+            if ucimode != UciMode::Ponder && ponder_move != NULLMOVE {
+                println!("bestmove {} ponder {}", best_move.to_algebraic(), ponder_move.to_algebraic());
+            } else if ucimode != UciMode::Ponder {
                 println!("bestmove {}", best_move.to_algebraic());
             }
         }
@@ -233,42 +599,65 @@ pub fn iterative_deepening<const SEARCHMODE: SearchProtocol>(mut thread_data: Th
     (best_move, best_eval)
 }
 
-fn alpha_beta<const SEARCHMODE: SearchProtocol>(alpha: Eval, beta: Eval, state: &mut GameState, depth: u8, search_info: &mut SearchInfo, trans_table: *mut LockLessTransTable, do_null: bool, check_extensions: u8) -> Eval {
+/// Returns 0 once `search_info.stopped` is set, callers must check it before using the score.
+/// `cut_node` marks zero window nodes expected to fail high.
+fn alpha_beta<const SEARCHMODE: SearchProtocol>(alpha: Eval, beta: Eval, state: &mut GameState, depth: Depth, search_info: &mut SearchInfo, trans_table: *mut LockLessTransTable, do_null: bool, cut_node: bool) -> Eval {
     search_info.nodes_searched += 1;
-    
-    if depth == 0 {
-        return quiescent_search::<SEARCHMODE>(state, alpha, beta, MAX_QUIESCENT_DEPTH, search_info)
+    if search_info.should_stop::<SEARCHMODE>() {
+        return 0;
+    }
+    let ply = state.search_ply as usize;
+    if ply < MAX_PLY {
+        search_info.pv_table.clear(ply);
     }
 
-    if (state.has_repitition() || state.fifty_move_rule >= 100) {
-        return 0;
+    if depth <= 0 {
+        return quiescent_search::<SEARCHMODE>(state, alpha, beta, 0, search_info, trans_table)
     }
 
-    let mut depth = depth;
-    let mut check_extensions = check_extensions;
-    let in_check = state.is_in_check();
-    
-    if in_check && check_extensions <= MAX_CHECK_EXTENSIONS {
-        depth += 1;
-        check_extensions += 1;
+    // The root has to come up with a move, so it searches on.
+    if ply > 0 && (state.has_repitition() || state.fifty_move_rule >= 100 || state.is_drawn_ending()) {
+        return search_info.draw_score(ply);
+    }
+
+    if state.search_ply as usize >= MAX_PLY {
+        return state.static_eval();
+    }
+
+    // Mate distance pruning: no line from here beats a mate already found closer to the root.
+    let alpha = max(alpha, mated_in(ply));
+    let beta = min(beta, mate_in(ply + 1));
+    if alpha >= beta {
+        return alpha;
     }
 
+    let in_check = state.is_in_check();
+    let is_pv = beta - alpha > 1;
+    let excluded_move = search_info.stack[ply].excluded_move;
+
     let mut alpha = alpha;
+    // A reversible move back into the search tree is available, so the side to move holds at least the draw.
+    let draw_score = search_info.draw_score(ply);
+    if alpha < draw_score && state.has_upcoming_repetition() {
+        alpha = draw_score;
+        if alpha >= beta {
+            return beta;
+        }
+    }
     let original_alpha = alpha;
+    let params = unsafe { &SEARCH_PARAMS };
 
     let mut pvmove = NULLMOVE;
+    let mut tt_hit = None;
 
     unsafe {
         if let Some(entry) = (*trans_table).get(state.zobrist) {
             pvmove = entry.best_move();
-            assert!(pvmove != NULLMOVE);
-            let mut value = entry.value();
-            if value > ISMATE {
-                value -= state.plys as Eval;
-            } else if value < -ISMATE {
-                value += state.plys as Eval;
-            }
-            if entry.depth() >= depth {
+            let value = score_from_tt(entry.value(), ply);
+            tt_hit = Some((value, entry.depth() as Depth, entry.flag()));
+            // The entry belongs to the full node, so it must not cut off a search without the excluded move.
+            // PV nodes search on so the principal variation is never cut short.
+            if entry.depth() as Depth >= depth && excluded_move == NULLMOVE && !is_pv {
                 match entry.flag() {
                     LockLessFlag::Alpha => {
                         if value <= alpha {
@@ -288,22 +677,91 @@ fn alpha_beta<const SEARCHMODE: SearchProtocol>(alpha: Eval, beta: Eval, state:
         }
     }
 
+    // Internal iterative reduction: without a hash move the ordering is poor, so spend less on this node
+    // now and let the next iteration search it again with the move found here. All-nodes search every
+    // move anyway, the ordering hardly matters there.
+    let mut depth = depth;
+    if (is_pv || cut_node) && pvmove == NULLMOVE && excluded_move == NULLMOVE && depth >= params.iir_min_depth {
+        depth -= 1;
+    }
+
+    let static_eval = if in_check {
+        -AB_BOUND
+    } else {
+        state.static_eval()
+    };
+    search_info.stack[ply].static_eval = static_eval;
+    let improving = !in_check && ply >= 2 && static_eval > search_info.stack[ply - 2].static_eval;
+
+    if !is_pv && !in_check && excluded_move == NULLMOVE && beta.abs() < ISMATE {
+        // Reverse futility pruning
+        if depth <= params.rfp_max_depth && static_eval - params.rfp_margin * depth >= beta {
+            return beta;
+        }
+
+        // Razoring
+        if depth <= params.razor_max_depth && static_eval + params.razor_margin * depth < alpha {
+            let value = quiescent_search::<SEARCHMODE>(state, alpha - 1, alpha, 0, search_info, trans_table);
+            if search_info.stopped {
+                return 0;
+            }
+            if value < alpha {
+                return alpha;
+            }
+        }
+    }
+
     // Null-Move heuristic
-    if do_null && depth >= 4 && !in_check && state.phase() <= 220 && state.search_ply > 0 {
+    if do_null && depth >= params.nmp_min_depth && !in_check && excluded_move == NULLMOVE && static_eval >= beta && state.phase() <= 220 && state.search_ply > 0 {
+        let eval_reduction = min((static_eval - beta) / params.nmp_eval_divisor, params.nmp_max_eval_reduction);
+        let reduction = params.nmp_base_reduction + depth / params.nmp_depth_divisor + eval_reduction;
+        search_info.stack[ply].r#move = NULLMOVE;
         state.make_null_move();
-        let null_move_value = -alpha_beta::<SEARCHMODE>(-beta, -beta + 1, state, depth - 4, search_info, trans_table, false, check_extensions);
+        let null_move_value = -alpha_beta::<SEARCHMODE>(-beta, -beta + 1, state, depth - 1 - reduction, search_info, trans_table, false, !cut_node);
         state.undo_null_move();
+        if search_info.stopped {
+            return 0;
+        }
         if null_move_value >= beta && null_move_value.abs() < ISMATE {
             return beta;
         }
     }
 
 
+    // Singular extension: extend the TT move if every other move fails low against a margin below its score.
+    let mut singular_extension = 0;
+    if let Some((tt_value, tt_depth, tt_flag)) = tt_hit {
+        if depth >= params.se_min_depth
+            && ply > 0
+            && pvmove != NULLMOVE
+            && excluded_move == NULLMOVE
+            && tt_flag != LockLessFlag::Alpha
+            && tt_depth >= depth - params.se_tt_depth_margin
+            && tt_value.abs() < ISMATE {
+            let singular_beta = tt_value - params.se_margin * depth;
+            search_info.stack[ply].excluded_move = pvmove;
+            let value = alpha_beta::<SEARCHMODE>(singular_beta - 1, singular_beta, state, (depth - 1) / 2, search_info, trans_table, false, cut_node);
+            search_info.stack[ply].excluded_move = NULLMOVE;
+            if search_info.stopped {
+                return 0;
+            }
+            if value < singular_beta {
+                singular_extension = if !is_pv && value < singular_beta - params.se_double_margin { 2 } else { 1 };
+            } else if singular_beta >= beta {
+                // Multi-cut: more than one move beats beta, so this node will very likely fail high as well.
+                return beta;
+            }
+        }
+    }
+    // Extensions are limited to paths no longer than twice the iteration depth.
+    let can_extend = ply < 2 * search_info.search_depth as usize;
+
     let mut legals = 0;
 
     let mut moves = state.generate_pseudo_legal_moves();
 
-    moves.value_moves(search_info, depth, state.side_to_move());
+    let side_to_move = state.side_to_move();
+    moves.value_moves(search_info, ply, side_to_move);
 
     if pvmove != NULLMOVE {
         for move_index in 0..moves.length {
@@ -313,77 +771,114 @@ fn alpha_beta<const SEARCHMODE: SearchProtocol>(alpha: Eval, beta: Eval, state:
         }
     }
 
+    let can_prune_quiets = !is_pv && !in_check && alpha.abs() < ISMATE;
+    let futile = can_prune_quiets && depth <= params.futility_max_depth
+        && static_eval + params.futility_base + params.futility_margin * depth as Eval <= alpha;
+    let late_move_limit = params.lmp_base + (depth * depth) as u32;
+
     let mut best_move = NULLMOVE;
     let mut best_value = -AB_BOUND;
+    let mut quiets_searched = 0;
+    let mut quiets_tried = MoveList::new();
+    let mut captures_tried = MoveList::new();
     for move_index in 0..moves.length {
         moves.highest_next_to_index(move_index);
         let r#move = moves.moves[move_index as usize];
-        if !state.apply_pseudo_legal_move(r#move) {
+        if r#move == excluded_move || !state.apply_pseudo_legal_move(r#move) {
             continue;
         }
         legals += 1;
-        let value = if depth > 3 && legals > 3 && !r#move.is_capture() && !r#move.is_promotion() && !in_check && search_info.killer_table.is_killer(depth, r#move).is_none() {
-            let reduction = if legals > 6 { 2 } else { 1 };
-            let tmp_value = -alpha_beta::<SEARCHMODE>(-beta, -alpha, state, depth - 1 - reduction, search_info, trans_table, true, check_extensions);
+        let is_quiet = !r#move.is_capture() && !r#move.is_promotion();
+        let gives_check = state.is_in_check();
+        let is_killer = search_info.killer_table.is_killer(ply, r#move).is_some();
+        let history_score = if is_quiet { search_info.quiet_history(ply, side_to_move, r#move) } else { 0 };
+        if is_quiet && can_prune_quiets && legals > 1 && !gives_check && !is_killer {
+            // Futility and late move pruning
+            if futile || (depth <= params.lmp_max_depth && quiets_searched >= late_move_limit) {
+                state.undo_move();
+                continue;
+            }
+        }
+        if is_quiet {
+            quiets_searched += 1;
+        }
+        let extension = if !can_extend {
+            0
+        } else if r#move == pvmove && singular_extension > 0 {
+            singular_extension
+        } else if (gives_check && depth > 1) || (is_pv && search_info.is_recapture(ply, r#move)) || state.is_passed_pawn_push(r#move, side_to_move) {
+            1
+        } else {
+            0
+        };
+        let new_depth = depth - 1 + extension;
+        search_info.stack[ply].r#move = r#move;
+        search_info.stack[ply].piece = r#move.moving_piece();
+        let value = if depth >= params.lmr_min_depth && legals > 1 + is_pv as u32 && is_quiet && !in_check {
+            let mut reduction = search_info.lmr_table[min(depth as usize, LMR_TABLE_SIZE - 1)][min(legals as usize, LMR_TABLE_SIZE - 1)] as Depth;
+            reduction -= is_pv as Depth + is_killer as Depth + gives_check as Depth;
+            reduction += !improving as Depth;
+            reduction -= history_score / params.lmr_history_divisor;
+            // Not `clamp`, `new_depth` is 0 once a tuned `lmr_min_depth` lets depth 1 nodes reduce.
+            let reduction = reduction.min(new_depth - 1).max(0);
+            let tmp_value = -alpha_beta::<SEARCHMODE>(-alpha - 1, -alpha, state, new_depth - reduction, search_info, trans_table, true, true);
             if tmp_value > alpha {
-                -alpha_beta::<SEARCHMODE>(-beta, -alpha, state, depth - 1, search_info, trans_table, true, check_extensions)
+                -alpha_beta::<SEARCHMODE>(-beta, -alpha, state, new_depth, search_info, trans_table, true, !is_pv && !cut_node)
             } else {
                 tmp_value
             }
         } else {
-            -alpha_beta::<SEARCHMODE>(-beta, -alpha, state, depth - 1, search_info, trans_table, true, check_extensions)
+            -alpha_beta::<SEARCHMODE>(-beta, -alpha, state, new_depth, search_info, trans_table, true, !is_pv && !cut_node)
         };
         
         state.undo_move();
-        unsafe {            
-            if (SEARCHMODE == SearchProtocol::Uci(UciMode::Movetime) || SEARCHMODE == SearchProtocol::Texel) && search_info.time_over() {
-                return alpha;
-            }
-            if *search_info.stop_flag.get() && SEARCHMODE != SearchProtocol::Uci(UciMode::Ponder) {
-                return alpha;
-            }
-            if (SEARCHMODE == SearchProtocol::Uci(UciMode::Ponder)) && !(*search_info.stop_flag.get()) {
-                return alpha;
-            }
+        if search_info.stopped {
+            return 0;
         }
         if value > best_value {
             best_value = value;
             best_move = r#move;
             if value > alpha {
+                search_info.pv_table.update(ply, r#move);
+                if ply == 0 {
+                    search_info.root_best = Some((r#move, value));
+                }
                 if value >= beta {
-
-                    if !r#move.is_capture() {
-                        search_info.killer_table.store_killer(depth, r#move);
+                    let bonus = history_bonus(depth);
+                    if is_quiet {
+                        search_info.killer_table.store_killer(ply, r#move);
+                        search_info.update_quiet_histories(ply, side_to_move, r#move, &quiets_tried, bonus);
                     }
+                    search_info.update_capture_histories(side_to_move, r#move, &captures_tried, bonus);
 
                     unsafe {
                         assert!(best_move != NULLMOVE);
-                        let mut beta = beta;
-                        if beta > ISMATE {
-                            beta += state.search_ply as Eval;
-                        } else if beta < -ISMATE {
-                            beta -= state.search_ply as Eval;
+                        if excluded_move == NULLMOVE {
+                            (*trans_table).insert(state.zobrist, state, beta, best_move, LockLessFlag::Beta, depth as u8);
                         }
-                        (*trans_table).insert(state.zobrist, &state, beta, best_move, LockLessFlag::Beta, depth);
                     }
 
                     return beta;
                 }
                 alpha = value;
-                if !r#move.is_capture() {
-                    search_info.history_table[state.side_to_move()][r#move.from()][r#move.to()] += depth as u32;
-                }
             }
         }
-        
+        if is_quiet {
+            quiets_tried.add_move(r#move);
+        } else if r#move.is_capture() {
+            captures_tried.add_move(r#move);
+        }
     }
 
     if legals == 0 {
+        if excluded_move != NULLMOVE {
+            return alpha;
+        }
         if in_check {
-            return -AB_BOUND + (state.search_ply as Eval);
+            return mated_in(ply);
         } 
         else {
-            return 0;
+            return search_info.draw_score(ply);
         }
     }
 
@@ -400,61 +895,136 @@ fn alpha_beta<const SEARCHMODE: SearchProtocol>(alpha: Eval, beta: Eval, state:
 
     assert!(best_move != NULLMOVE);
     assert!(alpha >= original_alpha);
+    assert!(!search_info.stopped);
+    if excluded_move != NULLMOVE {
+        return alpha;
+    }
     unsafe {
         if alpha != original_alpha {
-            (*trans_table).insert(state.zobrist, state, best_value, best_move, LockLessFlag::Exact, depth);
+            (*trans_table).insert(state.zobrist, state, best_value, best_move, LockLessFlag::Exact, depth as u8);
         } else {
-            (*trans_table).insert(state.zobrist, state, best_value, best_move, LockLessFlag::Alpha, depth);
+            (*trans_table).insert(state.zobrist, state, best_value, best_move, LockLessFlag::Alpha, depth as u8);
         }
     }
 
     alpha
 }
 
-pub fn quiescent_search<const SEARCHMODE: SearchProtocol>(state: &mut GameState, alpha: Eval, beta: Eval, depth: u8, search_info: &mut SearchInfo) -> Eval {
+pub fn quiescent_search<const SEARCHMODE: SearchProtocol>(state: &mut GameState, alpha: Eval, beta: Eval, ply: u8, search_info: &mut SearchInfo, trans_table: *mut LockLessTransTable) -> Eval {
     search_info.nodes_searched += 1;
-    if state.has_repitition() || state.fifty_move_rule >= 100 {
+    if search_info.should_stop::<SEARCHMODE>() {
         return 0;
     }
-
-    let stand_pat: Eval = state.static_eval();
-    if depth == 0 {
-        return stand_pat;
+    // Only a reversible move can repeat a position: the move into the first ply, quiet checks and quiet
+    // evasions. Captures and pawn moves reset the clock. The ply cap ends check chains that never repeat.
+    if state.fifty_move_rule > 0 && (state.has_repitition() || state.fifty_move_rule >= 100) {
+        return search_info.draw_score(state.search_ply as usize);
     }
-    let mut alpha = alpha;
-    if stand_pat >= beta {
-        return beta;
+    if state.search_ply as usize >= MAX_PLY {
+        return state.static_eval();
     }
-    if alpha < stand_pat {
-        alpha = stand_pat;
+
+    let mut alpha = alpha;
+    let original_alpha = alpha;
+
+    let mut tt_move = NULLMOVE;
+    unsafe {
+        if let Some(entry) = (*trans_table).get(state.zobrist) {
+            tt_move = entry.best_move();
+            let value = score_from_tt(entry.value(), state.search_ply as usize);
+            match entry.flag() {
+                LockLessFlag::Alpha => {
+                    if value <= alpha {
+                        return alpha;
+                    }
+                },
+                LockLessFlag::Beta => {
+                    if value >= beta {
+                        return beta;
+                    }
+                },
+                LockLessFlag::Exact => {
+                    return value;
+                }
+            }
+        }
     }
 
-    let mut orderd_moves = state.generate_pseudo_legal_captures();
+    let in_check = state.is_in_check();
+    let stand_pat = if in_check {
+        -AB_BOUND
+    } else {
+        state.static_eval()
+    };
+    let mut orderd_moves = if in_check {
+        // No standing pat while in check, every evasion has to be searched.
+        let evasions = state.generate_evasions();
+        if evasions.length == 0 {
+            return mated_in(state.search_ply as usize);
+        }
+        evasions
+    } else {
+        if stand_pat >= beta {
+            return beta;
+        }
+        if alpha < stand_pat {
+            alpha = stand_pat;
+        }
+        let mut captures = state.generate_pseudo_legal_captures();
+        if ply == 0 {
+            for r#move in state.generate_quiet_checks() {
+                captures.add_move(r#move);
+            }
+        }
+        captures
+    };
     orderd_moves.value_moves_mvv_lva();
+    if tt_move != NULLMOVE {
+        for move_index in 0..orderd_moves.length {
+            if orderd_moves.moves[move_index as usize] == tt_move {
+                orderd_moves.values[move_index as usize] = u32::MAX;
+            }
+        }
+    }
+
+    let mut best_move = NULLMOVE;
     for move_index in 0..orderd_moves.length {
         orderd_moves.highest_next_to_index(move_index);
         let r#move = orderd_moves.moves[move_index as usize];
+        if !in_check && !r#move.is_promotion() {
+            // Delta pruning
+            if r#move.is_capture() && stand_pat + unsafe { EVAL_PARAMS.mg_piece_value[r#move.captured_piece()] } + DELTA_MARGIN <= alpha {
+                continue;
+            }
+            if !state.see_ge(r#move, 0) {
+                continue;
+            }
+        }
         if !state.apply_pseudo_legal_move(r#move) {
             continue;
         }
-        let score = -quiescent_search::<SEARCHMODE>(state, -beta, -alpha, depth - 1, search_info);
+        let score = -quiescent_search::<SEARCHMODE>(state, -beta, -alpha, ply + 1, search_info, trans_table);
         state.undo_move();
-        unsafe {
-                if (SEARCHMODE == SearchProtocol::Uci(UciMode::Movetime) || SEARCHMODE == SearchProtocol::Texel) && search_info.time_over() {
-                    return alpha;
-                }
-                if *search_info.stop_flag.get() && SEARCHMODE != SearchProtocol::Uci(UciMode::Ponder) {
-                    return alpha;
-                }
-                if (SEARCHMODE == SearchProtocol::Uci(UciMode::Ponder)) && !(*search_info.stop_flag.get()) {
-                    return alpha;
-                }
+        if search_info.stopped {
+            return 0;
         }
         if score >= beta {
+            unsafe {
+                (*trans_table).insert(state.zobrist, state, beta, r#move, LockLessFlag::Beta, 0);
+            }
             return beta;
         }
         if score > alpha {
             alpha = score;
+            best_move = r#move;
+        }
+    }
+
+    unsafe {
+        if alpha != original_alpha {
+            (*trans_table).insert(state.zobrist, state, alpha, best_move, LockLessFlag::Exact, 0);
+        } else {
+            (*trans_table).insert(state.zobrist, state, alpha, best_move, LockLessFlag::Alpha, 0);
         }
     }
     alpha
@@ -469,25 +1039,34 @@ static MVV_LVA: [[u32; NUM_OF_PIECES]; NUM_OF_PIECES] = [
     [0, 0, 0, 0, 0, 0], // Victim King
 ];
 
-const MVV_LVA_VALUE: u32 = u32::MAX - 1000;
+const MVV_LVA_VALUE: u32 = u32::MAX - (1 << 24);
+/// Capture history only breaks ties between captures of equal `MVV_LVA` score.
+const CAPTURE_HISTORY_RANGE: u32 = 2 * HISTORY_MAX as u32 + 1;
 const KILLER_VALUE: u32 = MVV_LVA_VALUE - 1000;
 const SECONDARY_KILLER_VALUE: u32 = KILLER_VALUE - 1000;
+const COUNTER_MOVE_VALUE: u32 = SECONDARY_KILLER_VALUE - 1000;
+/// Shifts the signed quiet history into the positive range of move values.
+const QUIET_HISTORY_OFFSET: i32 = (CONTINUATION_PLIES as i32 + 1) * HISTORY_MAX;
 
 impl MoveList {
-    fn value_moves(&mut self, search_info: &mut SearchInfo, depth: u8, side_to_move: Side) {
+    fn value_moves(&mut self, search_info: &SearchInfo, ply: usize, side_to_move: Side) {
+        let counter_move = search_info.counter_move(ply, side_to_move);
         for move_index in 0..self.length {
             let r#move = self.moves[move_index as usize];
             if r#move.is_capture() {
-                self.values[move_index as usize] += MVV_LVA_VALUE + MVV_LVA[r#move.captured_piece()][r#move.moving_piece()];
-            } else if let Some(index) = search_info.killer_table.is_killer(depth, r#move) {
+                let capture_history = (search_info.capture_history(side_to_move, r#move) + HISTORY_MAX) as u32;
+                self.values[move_index as usize] += MVV_LVA_VALUE + MVV_LVA[r#move.captured_piece()][r#move.moving_piece()] * CAPTURE_HISTORY_RANGE + capture_history;
+            } else if let Some(index) = search_info.killer_table.is_killer(ply, r#move) {
                 if index == 0 {
                     self.values[move_index as usize] += KILLER_VALUE;
                 } else if index == 1 {
                     self.values[move_index as usize] += SECONDARY_KILLER_VALUE;
                 }
+            } else if r#move == counter_move {
+                self.values[move_index as usize] += COUNTER_MOVE_VALUE;
             } else {
-                self.values[move_index as usize] += search_info.history_table[side_to_move][r#move.from()][r#move.to()];
-                assert!(self.values[move_index as usize] < SECONDARY_KILLER_VALUE);
+                self.values[move_index as usize] += (search_info.quiet_history(ply, side_to_move, r#move) + QUIET_HISTORY_OFFSET) as u32;
+                assert!(self.values[move_index as usize] < COUNTER_MOVE_VALUE);
             }
         }
     }
@@ -516,4 +1095,114 @@ impl MoveList {
         self.values[index2 as usize] = temp_value;
         self.moves[index2 as usize] = temp_move;
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::{cell::SyncUnsafeCell, sync::Arc, thread, time::Duration};
+
+    use crate::{gamestate::GameState, lockless::{LockLessFlag, LockLessTransTable}, timeman::TimeManager};
+
+    use super::{iterative_deepening, moves_to_mate, quiescent_search, Depth, Eval, Move, SearchInfo, SearchLimits, SearchProtocol, ThreadData, AB_BOUND, ISMATE, NULLMOVE};
+
+    /// Best move and score of a fixed depth search, run on a big stack as unoptimized builds
+    /// construct the transposition table in place.
+    fn search_fixed(fen: &'static str, depth: Depth) -> (Move, Eval) {
+        thread::Builder::new().stack_size(64 << 20).spawn(move || {
+            let mut thread_data = ThreadData {
+                state: GameState::new_from_fen(fen),
+                trans_table: Arc::new(SyncUnsafeCell::new(LockLessTransTable::new())),
+                thread_num: 0,
+                search_info: SearchInfo::new(SearchLimits::new(TimeManager::fixed(Duration::MAX), depth as u8 + 1), Arc::new(SyncUnsafeCell::new(false))),
+            };
+            iterative_deepening::<{SearchProtocol::Debug}>(&mut thread_data)
+        }).unwrap().join().unwrap()
+    }
+
+    fn search_mate(fen: &'static str, depth: Depth) -> Option<i32> {
+        let (_, score) = search_fixed(fen, depth);
+        (score.abs() >= ISMATE).then(|| moves_to_mate(score))
+    }
+
+    #[test]
+    fn test_mate_in_n() {
+        // Back rank mate.
+        assert_eq!(search_mate("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1", 4), Some(1));
+        // 1. Kc7 Ka7 2. Ra1#
+        assert_eq!(search_mate("k7/8/2K5/8/8/8/8/1R6 w - - 0 1", 6), Some(2));
+        // Every pawn move runs into Qf1# or Qh2#.
+        assert_eq!(search_mate("8/8/8/8/8/6k1/P4q2/7K w - - 0 1", 6), Some(-1));
+        // The king has to walk to c7 first.
+        assert_eq!(search_mate("k7/8/8/2K5/8/8/8/1R6 w - - 0 1", 8), Some(3));
+    }
+    #[test]
+    fn test_drawn_roots_return_a_move() {
+        for fen in [
+            "8/8/8/4k3/8/8/8/4K3 w - - 0 1",
+            "8/8/8/4k3/8/8/8/4K2N w - - 0 1",
+            "8/8/8/4k3/8/3P4/8/4K3 w - - 100 80",
+        ] {
+            let (best_move, _) = search_fixed(fen, 4);
+            assert!(best_move != NULLMOVE, "{fen}");
+        }
+    }
+    #[test]
+    fn test_node_limit_in_first_iteration() {
+        thread::Builder::new().stack_size(64 << 20).spawn(|| {
+            let limits = SearchLimits { max_nodes: 50, ..SearchLimits::new(TimeManager::fixed(Duration::MAX), 10) };
+            let mut thread_data = ThreadData {
+                state: GameState::new_from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1"),
+                trans_table: Arc::new(SyncUnsafeCell::new(LockLessTransTable::new())),
+                thread_num: 0,
+                search_info: SearchInfo::new(limits, Arc::new(SyncUnsafeCell::new(false))),
+            };
+            let (best_move, _) = iterative_deepening::<{SearchProtocol::Texel}>(&mut thread_data);
+            assert!(thread_data.search_info.nodes_searched <= 51);
+            assert!(best_move != NULLMOVE);
+        }).unwrap().join().unwrap();
+    }
+    #[test]
+    fn test_quiescence_repetition_after_quiet_evasion() {
+        thread::Builder::new().stack_size(64 << 20).spawn(|| {
+            // White is down a rook for a pawn, but Qc4+ Kh8 repeats the position after the first move a third time.
+            let mut state = GameState::new_from_fen("rr3b1k/6pp/8/8/2Q5/8/8/7K w - - 0 1");
+            for text_move in ["c4c3", "h8g8", "c3c4", "g8h8", "c4c3", "h8g8"] {
+                state.apply_legal_move(Move::from_text_move(&state, text_move));
+            }
+            state.search_ply = 0;
+            let trans_table = Arc::new(SyncUnsafeCell::new(LockLessTransTable::new()));
+            let mut search_info = SearchInfo::new(SearchLimits::new(TimeManager::fixed(Duration::MAX), 2), Arc::new(SyncUnsafeCell::new(false)));
+            let score = quiescent_search::<{SearchProtocol::Debug}>(&mut state, -AB_BOUND, AB_BOUND, 0, &mut search_info, trans_table.get());
+            assert!(score.abs() <= 1, "{score}");
+        }).unwrap().join().unwrap();
+    }
+    #[test]
+    fn test_interrupted_iteration_keeps_the_previous_best() {
+        thread::Builder::new().stack_size(64 << 20).spawn(|| {
+            // Interrupts the depth 5 iteration after ever more nodes, until the hanging Nxe5 that a helper thread
+            // left as the root hash move is the best move searched so far.
+            for extra_nodes in (0..16).map(|shift| 64 << shift) {
+                let mut thread_data = ThreadData {
+                    state: GameState::new_from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3"),
+                    trans_table: Arc::new(SyncUnsafeCell::new(LockLessTransTable::new())),
+                    thread_num: 0,
+                    search_info: SearchInfo::new(SearchLimits::new(TimeManager::fixed(Duration::MAX), 5), Arc::new(SyncUnsafeCell::new(false))),
+                };
+                let (previous_best, previous_eval) = iterative_deepening::<{SearchProtocol::Debug}>(&mut thread_data);
+                let blunder = thread_data.state.generate_legal_moves().into_iter().find(|r#move| r#move.to_algebraic() == "f3e5").unwrap();
+                assert!(previous_best != blunder);
+                unsafe {
+                    *thread_data.search_info.stop_flag.get() = false;
+                    (*thread_data.trans_table.get()).insert(thread_data.state.zobrist, &thread_data.state, 0, blunder, LockLessFlag::Exact, 4);
+                }
+                thread_data.search_info.limits.max_nodes = thread_data.search_info.nodes_searched + extra_nodes;
+                thread_data.search_root::<{SearchProtocol::Debug}>(5);
+                assert!(thread_data.search_info.stopped);
+                if thread_data.search_info.root_best.is_some_and(|(r#move, _)| r#move == blunder) {
+                    assert_eq!(thread_data.search_info.interrupted_root_move(previous_best, previous_eval), None);
+                    return;
+                }
+            }
+            panic!("the iteration never stopped right after the hash move");
+        }).unwrap().join().unwrap();
+    }
+}
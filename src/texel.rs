@@ -1,10 +1,8 @@
-use std::{time::{Duration, Instant}, sync::{Arc, Mutex}, fs::File, io::{Write, BufReader, BufRead}, arch::x86_64::_MM_FROUND_NINT, cell::{UnsafeCell, SyncUnsafeCell}, thread};
-
-use rand::{distributions::WeightedIndex, thread_rng, prelude::*};
+use std::{time::{Duration, Instant}, sync::Arc, fs::File, io::{Write, BufReader, BufRead}, cell::SyncUnsafeCell, thread};
 
 use std::fs::OpenOptions;
 
-use crate::{gamestate::{GameState, BLACK, Side, WHITE}, book::OPENING_BOOK, r#move::Move, smpsearch::{Eval, iterative_deepening, SearchProtocol, search, quiescent_search, AB_BOUND, SearchInfo, INFINITY, UciMode, ISMATE}, lockless::LockLessTransTable, eval::print_relevant_params};
+use crate::{gamestate::{GameState, BLACK, Side, WHITE}, r#move::Move, smpsearch::{Eval, SearchProtocol, search, mated_in, INFINITY, SearchLimits}, lockless::LockLessTransTable, eval::{print_relevant_params, EvalParams, EVAL_PARAMS, MATERIAL_VALUE, TUNED_PARAMS}, timeman::TimeManager};
 
 pub fn generate_texel_sample_threaded(samples: u32, movetime: Duration, simul_threads: u8) -> String {
     let mut texel_samples = String::new();
@@ -57,10 +55,11 @@ pub fn texel_game(total_time: u64, increment: u64) -> String {
             fen_record.push('\n');
         }
 
-        let movetime = Duration::from_millis(gamestate.calculate_movetime(wtime, btime, increment, increment));
+        let time_left = if gamestate.side_to_move() == WHITE { wtime } else { btime };
+        let time_manager = TimeManager::from_clock(&gamestate, time_left, increment, None, 0);
 
         let timer = Instant::now();
-        let result = search::<{ SearchProtocol::Texel }>(1, movetime, gamestate.clone(), Arc::clone(&stop_flag), 20, Arc::clone(&trans_table));
+        let result = search::<{ SearchProtocol::Texel }>(1, SearchLimits::new(time_manager, 20), gamestate.clone(), Arc::clone(&stop_flag), Arc::clone(&trans_table));
         let elapsed = timer.elapsed();
 
         if gamestate.side_to_move() == WHITE {
@@ -76,7 +75,7 @@ pub fn texel_game(total_time: u64, increment: u64) -> String {
         gamestate.apply_legal_move(result.0);
         
     }
-    let winner: f32 = if gamestate.fifty_move_rule >= 100 || gamestate.has_repitition() {
+    let winner: f32 = if gamestate.fifty_move_rule >= 100 || gamestate.is_threefold_repetition() {
         0.5
     } else if gamestate.is_game_over() {
         if gamestate.is_in_check() {
@@ -117,8 +116,13 @@ pub fn eval_into_white_viewpoint(value: Eval, side_to_move: Side) -> Eval {
 }
 
 pub fn read_texel_sample_file() -> Vec<(String, f64)> {
+    read_texel_samples("resources/texel.dat")
+}
+
+/// Lines of a reduced fen and the result from white's point of view.
+pub fn read_texel_samples(path: &str) -> Vec<(String, f64)> {
     let mut vec = vec![];
-    let file = File::open("resources/texel.dat").unwrap();
+    let file = File::open(path).unwrap();
     let reader = BufReader::new(file);
     for line in reader.lines() {
         let line = line.unwrap();
@@ -130,55 +134,142 @@ pub fn read_texel_sample_file() -> Vec<(String, f64)> {
     vec
 }
 
-pub fn mean_square_error(k: f64, fen_and_values: &Vec<(String, f64)>) -> f64 {
-    let mut error = 0.0;
-    for (n, (fen, value)) in fen_and_values.iter().enumerate() {
-        let mut gamestate = GameState::new_from_fen(&format!("{fen} 0 1"));
+/// Texel samples parsed once and kept in memory for every error computation.
+pub struct TexelPositions {
+    /// Positions with their result from white's point of view.
+    positions: Vec<(GameState, f64)>,
+    /// Whether the positions are the leaves of their quiescence search, which only need a static eval.
+    leaves: bool,
+}
+
+impl TexelPositions {
+    /// `resolve_leaves` replaces every position by the end of its principal variation in a quiescence search with the
+    /// engine's params. The error is much cheaper then, at the price of keeping the leaves while the params change.
+    pub fn load(samples: &[(String, f64)], resolve_leaves: bool) -> Self {
+        let positions = in_parallel(samples, |chunk| chunk.iter().map(|(fen, result)| {
+            let mut state = GameState::new_from_fen(&format!("{fen} 0 1"));
+            if resolve_leaves {
+                let mut pv = vec![];
+                quiescence(&mut state.clone(), unsafe { &EVAL_PARAMS }, -INFINITY, INFINITY, &mut pv);
+                for r#move in pv {
+                    state.apply_legal_move(r#move);
+                }
+            }
+            (state, *result)
+        }).collect::<Vec<_>>()).into_iter().flatten().collect();
+        TexelPositions { positions, leaves: resolve_leaves }
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+}
+
+/// Runs `f` on a chunk of `items` per core.
+fn in_parallel<T: Sync, R: Send>(items: &[T], f: impl Fn(&[T]) -> R + Sync) -> Vec<R> {
+    let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+    let chunk_size = items.len().div_ceil(threads).max(1);
+    thread::scope(|scope| {
+        let workers: Vec<_> = items.chunks(chunk_size).map(|chunk| scope.spawn(|| f(chunk))).collect();
+        workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+    })
+}
 
-        let eval = {
-            let tmp_eval = quiescent_search::<{SearchProtocol::Uci(UciMode::Infinite)}>(&mut gamestate, -INFINITY, INFINITY, 10, &mut SearchInfo::new(Duration::from_millis(0), Arc::new(SyncUnsafeCell::new(false))));
-            // let tmp_eval = gamestate.static_eval();
-            (if gamestate.side_to_move() == BLACK {
-                -tmp_eval
+/// Mean square error between the results and the evals with `params`, computed on every core.
+pub fn mean_square_error(k: f64, positions: &TexelPositions, params: &EvalParams) -> f64 {
+    let errors = in_parallel(&positions.positions, |chunk| {
+        let mut error = 0.0;
+        for (state, result) in chunk {
+            let eval = if positions.leaves {
+                state.static_eval_with(params)
             } else {
-                tmp_eval
-            }) as f64
-        };
+                quiescence(&mut state.clone(), params, -INFINITY, INFINITY, &mut vec![])
+            };
+            error += (result - sigmoid(k, eval_into_white_viewpoint(eval, state.side_to_move()) as f64)).powi(2);
+        }
+        error
+    });
+    errors.iter().sum::<f64>() / positions.len() as f64
+}
 
-        let sigmoid = 1_f64 / (1_f64 + 10_f64.powf((-k * eval) / 400_f64));
+/// Quiescence search on the eval with `params`, every evasion is searched in check. `pv` receives the moves to the
+/// position whose static eval is the score.
+fn quiescence(state: &mut GameState, params: &EvalParams, alpha: Eval, beta: Eval, pv: &mut Vec<Move>) -> Eval {
+    pv.clear();
+    let mut alpha = alpha;
+    let in_check = state.is_in_check();
+    let moves = if in_check {
+        state.generate_evasions()
+    } else {
+        let stand_pat = state.static_eval_with(params);
+        if stand_pat >= beta {
+            return beta;
+        }
+        alpha = alpha.max(stand_pat);
+        state.generate_pseudo_legal_captures()
+    };
+    // Most valuable victim first, least valuable attacker among equal victims.
+    let mut moves: Vec<Move> = moves.into_iter().collect();
+    moves.sort_by_key(|r#move| if r#move.is_capture() {
+        MATERIAL_VALUE[r#move.moving_piece()] - 16 * MATERIAL_VALUE[r#move.captured_piece()]
+    } else {
+        0
+    });
 
-        error += (value - sigmoid).powi(2);
+    let mut has_legal_move = false;
+    let mut line = vec![];
+    for r#move in moves {
+        if !state.apply_pseudo_legal_move(r#move) {
+            continue;
+        }
+        has_legal_move = true;
+        let score = -quiescence(state, params, -beta, -alpha, &mut line);
+        state.undo_move();
+        if score > alpha {
+            alpha = score;
+            pv.clear();
+            pv.push(r#move);
+            pv.extend_from_slice(&line);
+            if alpha >= beta {
+                break;
+            }
+        }
+    }
+    if in_check && !has_legal_move {
+        return mated_in(0);
     }
-    error / fen_and_values.len() as f64
+    alpha
 }
 
-pub fn optimize_params(params: Vec<*mut Eval>) {
-    let fen_and_values = read_texel_sample_file();
-    let mut best_e = mean_square_error(K, &fen_and_values);
+/// Expected score of an eval in centipawns, `k` scales it to the results.
+pub fn sigmoid(k: f64, eval: f64) -> f64 {
+    1_f64 / (1_f64 + 10_f64.powf((-k * eval) / 400_f64))
+}
+
+/// Coordinate descent of the tuned params in `params` on the error over `positions`.
+pub fn optimize_params(params: &mut EvalParams, positions: &TexelPositions) {
+    let tuned_params: usize = TUNED_PARAMS.iter().map(|(_, group)| group(params).len()).sum();
+    let mut best_e = mean_square_error(K, positions, params);
     let mut improved = true;
     while improved {
         
         improved = false;
-        for &param in params.iter() {
-            print_relevant_params();
+        for index in 0..tuned_params {
+            print_relevant_params(params);
             println!("Error: {best_e}\n");
-            unsafe {
-                *param += 1;
-            }
-            let new_e = mean_square_error(K, &fen_and_values);
+            *params.tuned_param_mut(index).unwrap() += 1;
+            let new_e = mean_square_error(K, positions, params);
             if new_e < best_e {
                 best_e = new_e;
                 improved = true;
             } else {
-                unsafe {
-                    *param -= 2;
-                    let new_e = mean_square_error(K, &fen_and_values);
-                    if new_e < best_e {
-                        best_e = new_e;
-                        improved = true;
-                    } else {
-                        *param += 1;
-                    }
+                *params.tuned_param_mut(index).unwrap() -= 2;
+                let new_e = mean_square_error(K, positions, params);
+                if new_e < best_e {
+                    best_e = new_e;
+                    improved = true;
+                } else {
+                    *params.tuned_param_mut(index).unwrap() += 1;
                 }
             }
         }
@@ -189,17 +280,17 @@ const DELTA_K: f64 = 0.0001_f64;
 pub const K: f64 = 0.598_f64;
 // const RIGHT_MAX: f64 = 15.00_f64;
 // const LEFT_MAX: f64 = 0.00_f64;
-pub fn find_smallest_k(fen_and_values: &Vec<(String, f64)>) -> f64 {
+pub fn find_smallest_k(positions: &TexelPositions, params: &EvalParams) -> f64 {
     // let mut left_max = LEFT_MAX;
     // let mut right_max = RIGHT_MAX;
     let mut best_k = 0.598_f64;
     let mut improved = true;
-    let mut best_e = mean_square_error(best_k, fen_and_values);
+    let mut best_e = mean_square_error(best_k, positions, params);
     while improved {
         improved = false;
         println!("Error: {best_e} \t k: {best_k}");
         let new_k = best_k + DELTA_K;
-        let new_e = mean_square_error(new_k, fen_and_values);
+        let new_e = mean_square_error(new_k, positions, params);
         if new_e < best_e {
             // left_max = best_k;
             best_e = new_e;
@@ -207,7 +298,7 @@ pub fn find_smallest_k(fen_and_values: &Vec<(String, f64)>) -> f64 {
             improved = true;
         } else {
             let new_k = best_k - DELTA_K;
-            let new_e = mean_square_error(new_k, fen_and_values);
+            let new_e = mean_square_error(new_k, positions, params);
             if new_e < best_e {
                 // right_max = best_k;
                 best_e = new_e;
@@ -217,4 +308,52 @@ pub fn find_smallest_k(fen_and_values: &Vec<(String, f64)>) -> f64 {
         }
     }
     best_k
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::eval::{EvalParams, EVAL_PARAMS, TUNED_PARAMS};
+    use crate::gamestate::PAWN;
+
+    use super::{mean_square_error, TexelPositions, K};
+
+    #[test]
+    fn test_resolved_leaves_keep_the_error() {
+        let samples: Vec<(String, f64)> = [
+            ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq -", 1.0),
+            ("rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq -", 0.5),
+            ("2r3k1/pp3ppp/2n1b3/q2pP3/3P4/P1PB1N2/5PPP/R2Q1RK1 b - -", 0.0),
+            ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - -", 0.5),
+        ].iter().map(|&(fen, result)| (fen.to_string(), result)).collect();
+        let mut params = unsafe { EVAL_PARAMS.clone() };
+        let searched = TexelPositions::load(&samples, false);
+        let leaves = TexelPositions::load(&samples, true);
+        let error = mean_square_error(K, &searched, &params);
+        assert!((error - mean_square_error(K, &leaves, &params)).abs() < 1e-12);
+
+        // Other params change the error without touching the engine's.
+        params.mg_piece_value[PAWN] += 50;
+        assert!((error - mean_square_error(K, &searched, &params)).abs() > 1e-6);
+        assert_eq!(unsafe { EVAL_PARAMS.mg_piece_value[PAWN] } + 50, params.mg_piece_value[PAWN]);
+    }
+    /// Index of the first entry of the tuned parameter group `name`.
+    fn tuned_param_index(params: &mut EvalParams, name: &str) -> usize {
+        assert!(TUNED_PARAMS.iter().any(|&(group, _)| group == name), "{name} isn't tuned");
+        TUNED_PARAMS.iter().take_while(|&&(group, _)| group != name).map(|(_, group)| group(params).len()).sum()
+    }
+
+    #[test]
+    fn test_tuned_params() {
+        let mut params = unsafe { EVAL_PARAMS.clone() };
+        let king_safety_table = tuned_param_index(&mut params, "king_safety_table");
+        let mg_passed = tuned_param_index(&mut params, "mg_passed");
+        let last = tuned_param_index(&mut params, "mg_space");
+        assert!(params.tuned_param_mut(last + 1).is_none());
+        *params.tuned_param_mut(king_safety_table + 20).unwrap() += 3;
+        // The passed pawn bonus of the first rank isn't tuned.
+        *params.tuned_param_mut(mg_passed).unwrap() += 2;
+        *params.tuned_param_mut(last).unwrap() += 1;
+        assert_eq!(params.king_safety_table[20], unsafe { EVAL_PARAMS.king_safety_table[20] } + 3);
+        assert_eq!(params.mg_passed[1], unsafe { EVAL_PARAMS.mg_passed[1] } + 2);
+        assert_eq!(params.mg_space, unsafe { EVAL_PARAMS.mg_space } + 1);
+    }
+}
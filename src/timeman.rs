@@ -0,0 +1,82 @@
+use std::time::{Duration, Instant};
+
+use crate::gamestate::GameState;
+use crate::r#move::Move;
+use crate::smpsearch::{Eval, NULLMOVE};
+
+/// The hard limit may use at most this many times the optimum.
+const MAXIMUM_FACTOR: f64 = 4.0;
+/// Share of the remaining clock a single move may never exceed.
+const MAXIMUM_CLOCK_SHARE: f64 = 0.75;
+/// A score drop bigger than this between iterations buys more time.
+const SCORE_DROP_MARGIN: Eval = 30;
+
+/// Decides how long a search may take and whether another iteration is worth starting.
+#[derive(Clone, Copy)]
+pub struct TimeManager {
+    start_time: Instant,
+    /// Target time of a search on the clock, fixed time searches have none.
+    optimum: Option<Duration>,
+    maximum: Duration,
+    best_move: Move,
+    best_move_stability: u32,
+    score: Option<Eval>,
+}
+
+impl TimeManager {
+    /// Searches exactly `movetime` without soft stops.
+    pub fn fixed(movetime: Duration) -> Self {
+        TimeManager { start_time: Instant::now(), optimum: None, maximum: movetime, best_move: NULLMOVE, best_move_stability: 0, score: None }
+    }
+
+    /// Splits the remaining clock over the expected number of moves, `moves_to_go` if the GUI sent one.
+    pub fn from_clock(state: &GameState, time_left: u64, increment: u64, moves_to_go: Option<u64>, move_overhead: u64) -> Self {
+        let time_left = time_left.saturating_sub(move_overhead).max(1) as f64;
+        let moves_to_go = moves_to_go.unwrap_or(state.moves_left() as u64).max(1) as f64;
+        let maximum_clock = time_left * MAXIMUM_CLOCK_SHARE;
+
+        let base = time_left / moves_to_go + increment as f64 * 0.75;
+        let optimum = (base * state.midgame_scale()).min(maximum_clock);
+        let maximum = (optimum * MAXIMUM_FACTOR).min(maximum_clock);
+
+        TimeManager {
+            optimum: Some(Duration::from_secs_f64(optimum / 1000.0)),
+            maximum: Duration::from_secs_f64(maximum / 1000.0),
+            ..Self::fixed(Duration::ZERO)
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start_time.elapsed()
+    }
+
+    pub fn hard_limit_reached(&self) -> bool {
+        self.elapsed() > self.maximum
+    }
+
+    /// Records the result of a finished iteration and tells if the next one should be started.
+    /// A stable best move shrinks the optimum, a changed best move or a dropping score stretches it.
+    pub fn continue_iterating(&mut self, best_move: Move, score: Eval) -> bool {
+        if best_move == self.best_move {
+            self.best_move_stability += 1;
+        } else {
+            self.best_move = best_move;
+            self.best_move_stability = 0;
+        }
+        let score_dropped = self.score.is_some_and(|previous| score < previous - SCORE_DROP_MARGIN);
+        self.score = Some(score);
+
+        let stability_scale = match self.best_move_stability {
+            0 => 1.5,
+            1 => 1.2,
+            2 => 1.0,
+            3 => 0.85,
+            _ => 0.7,
+        };
+        let score_scale = if score_dropped { 1.3 } else { 1.0 };
+        match self.optimum {
+            Some(optimum) => self.elapsed() < optimum.mul_f64(stability_scale * score_scale).min(self.maximum),
+            None => !self.hard_limit_reached(),
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use std::{env, fs, time::Instant};
+
+use clap::Parser;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{
+    gamestate::{GameState, KING, NUM_OF_PLAYERS, WHITE},
+    nnue::{Network, Orientation, FEATURES, HIDDEN, OUTPUT_SCALE, QA, QB},
+    texel::{read_texel_samples, sigmoid, K},
+};
+
+const FEATURE_BIAS: usize = FEATURES * HIDDEN;
+const OUTPUT_WEIGHTS: usize = FEATURE_BIAS + HIDDEN;
+const OUTPUT_BIAS: usize = OUTPUT_WEIGHTS + 2 * HIDDEN;
+
+/// Most pieces on the board, kings included.
+const MAX_ACTIVE_FEATURES: usize = 32;
+/// Keeps the quantised accumulator of 32 features and the bias inside `i16`.
+const MAX_FEATURE_WEIGHT: f32 = 32767.0 / (QA as f32 * (MAX_ACTIVE_FEATURES + 1) as f32);
+const MAX_OUTPUT_WEIGHT: f32 = 32767.0 / QB as f32;
+
+const BETA1: f32 = 0.9;
+const BETA2: f32 = 0.999;
+const EPSILON: f32 = 1e-8;
+
+#[derive(Parser, Debug)]
+struct TrainArgs {
+    /// Texel samples, lines of a reduced fen and the result from white's point of view.
+    #[arg(long, default_value = "resources/texel.dat")]
+    data: String,
+    /// Quantised network for the `EvalFile` option, rewritten after every epoch.
+    #[arg(long, default_value = "network.nnue")]
+    output: String,
+    #[arg(long, default_value_t = 10)]
+    epochs: usize,
+    #[arg(long, default_value_t = 16384)]
+    batch_size: usize,
+    #[arg(long, default_value_t = 0.001)]
+    learning_rate: f32,
+    /// Scaling of the WDL sigmoid, the `K` of the texel tuning.
+    #[arg(long, default_value_t = K)]
+    k: f64,
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+/// `train [--data <file>] [--output <file>] [--epochs <n>] ...`: fits a network to the texel samples with Adam.
+pub fn train() {
+    // Skips the program name, clap takes "train" for it.
+    let args = TrainArgs::parse_from(env::args().skip(1));
+    let samples = read_texel_samples(&args.data);
+    let mut positions: Vec<TrainingPosition> = samples.iter()
+        .map(|(fen, result)| TrainingPosition::new(&GameState::new_from_fen(&format!("{fen} 0 1")), *result as f32))
+        .collect();
+    println!("Training on {} positions", positions.len());
+
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    let mut trainer = Trainer::new(&mut rng, args.k as f32);
+    for epoch in 1..=args.epochs {
+        let start = Instant::now();
+        positions.shuffle(&mut rng);
+        let mut loss = 0.0;
+        for batch in positions.chunks(args.batch_size) {
+            loss += trainer.step(batch, args.learning_rate);
+        }
+        fs::write(&args.output, trainer.quantise().to_bytes()).expect("Couldn't write the network.");
+        println!("Epoch {epoch}: loss {:.6}, {:.1}s", loss / positions.len() as f32, start.elapsed().as_secs_f32());
+    }
+}
+
+/// Active features of both perspectives, the side to move's first, and the result for the side to move.
+struct TrainingPosition {
+    features: [[u16; MAX_ACTIVE_FEATURES]; NUM_OF_PLAYERS],
+    length: [usize; NUM_OF_PLAYERS],
+    target: f32,
+}
+
+impl TrainingPosition {
+    fn new(state: &GameState, white_result: f32) -> Self {
+        let side_to_move = state.side_to_move();
+        let mut position = TrainingPosition {
+            features: [[0; MAX_ACTIVE_FEATURES]; NUM_OF_PLAYERS],
+            length: [0; NUM_OF_PLAYERS],
+            target: if side_to_move == WHITE { white_result } else { 1.0 - white_result },
+        };
+        for (index, perspective) in [side_to_move, side_to_move ^ 1].into_iter().enumerate() {
+            let orientation = Orientation::new(perspective, state.piece_boards[perspective][KING].next_piece_index());
+            for (side, boards) in state.piece_boards.iter().enumerate() {
+                for (piece, &board) in boards.iter().enumerate() {
+                    for square in board {
+                        position.features[index][position.length[index]] = orientation.feature(perspective, square, piece, side) as u16;
+                        position.length[index] += 1;
+                    }
+                }
+            }
+        }
+        position
+    }
+
+    fn active(&self, index: usize) -> &[u16] {
+        &self.features[index][..self.length[index]]
+    }
+}
+
+/// Float weights laid out like the fields of `Network`, with their gradients and Adam moments.
+struct Trainer {
+    weights: Vec<f32>,
+    gradients: Vec<f32>,
+    momentum: Vec<f32>,
+    velocity: Vec<f32>,
+    steps: i32,
+    k: f32,
+}
+
+impl Trainer {
+    fn new(rng: &mut StdRng, k: f32) -> Self {
+        let mut weights = vec![0.0; Network::PARAMETERS];
+        let feature_range = 1.0 / (MAX_ACTIVE_FEATURES as f32).sqrt();
+        let output_range = 1.0 / (2.0 * HIDDEN as f32).sqrt();
+        for weight in &mut weights[..FEATURE_BIAS] {
+            *weight = rng.gen_range(-feature_range..feature_range);
+        }
+        for weight in &mut weights[OUTPUT_WEIGHTS..OUTPUT_BIAS] {
+            *weight = rng.gen_range(-output_range..output_range);
+        }
+        Trainer {
+            weights,
+            gradients: vec![0.0; Network::PARAMETERS],
+            momentum: vec![0.0; Network::PARAMETERS],
+            velocity: vec![0.0; Network::PARAMETERS],
+            steps: 0,
+            k,
+        }
+    }
+
+    /// Clipped ReLU activations of both perspectives, 1.0 stands for `QA`.
+    fn hidden(&self, position: &TrainingPosition) -> [[f32; HIDDEN]; NUM_OF_PLAYERS] {
+        let mut hidden = [[0.0; HIDDEN]; NUM_OF_PLAYERS];
+        for (index, values) in hidden.iter_mut().enumerate() {
+            values.copy_from_slice(&self.weights[FEATURE_BIAS..OUTPUT_WEIGHTS]);
+            for &feature in position.active(index) {
+                let column = &self.weights[feature as usize * HIDDEN..(feature as usize + 1) * HIDDEN];
+                for (value, weight) in values.iter_mut().zip(column) {
+                    *value += weight;
+                }
+            }
+        }
+        hidden
+    }
+
+    /// Network output before the `OUTPUT_SCALE`, for the side to move.
+    fn output(&self, hidden: &[[f32; HIDDEN]; NUM_OF_PLAYERS]) -> f32 {
+        let output_weights = &self.weights[OUTPUT_WEIGHTS..OUTPUT_BIAS];
+        hidden.iter().flatten().zip(output_weights).map(|(value, weight)| value.clamp(0.0, 1.0) * weight).sum::<f32>()
+            + self.weights[OUTPUT_BIAS]
+    }
+
+    /// Win probability of the side to move, the sigmoid of the texel tuning.
+    fn predict(&self, output: f32) -> f32 {
+        sigmoid(self.k as f64, (output * OUTPUT_SCALE as f32) as f64) as f32
+    }
+
+    /// One Adam step on the mean squared error of `batch`, returns the summed error.
+    fn step(&mut self, batch: &[TrainingPosition], learning_rate: f32) -> f32 {
+        self.gradients.iter_mut().for_each(|gradient| *gradient = 0.0);
+        let mut loss = 0.0;
+        // Derivative of the sigmoid's argument with respect to the output.
+        let scale = OUTPUT_SCALE as f32 * self.k * std::f32::consts::LN_10 / 400.0;
+        for position in batch {
+            let hidden = self.hidden(position);
+            let prediction = self.predict(self.output(&hidden));
+            let error = prediction - position.target;
+            loss += error * error;
+
+            let output_gradient = 2.0 * error * prediction * (1.0 - prediction) * scale / batch.len() as f32;
+            self.gradients[OUTPUT_BIAS] += output_gradient;
+            for (index, values) in hidden.iter().enumerate() {
+                for (neuron, &value) in values.iter().enumerate() {
+                    let output_weight = OUTPUT_WEIGHTS + index * HIDDEN + neuron;
+                    self.gradients[output_weight] += output_gradient * value.clamp(0.0, 1.0);
+                    if value <= 0.0 || value >= 1.0 {
+                        continue;
+                    }
+                    let hidden_gradient = output_gradient * self.weights[output_weight];
+                    self.gradients[FEATURE_BIAS + neuron] += hidden_gradient;
+                    for &feature in position.active(index) {
+                        self.gradients[feature as usize * HIDDEN + neuron] += hidden_gradient;
+                    }
+                }
+            }
+        }
+
+        self.steps += 1;
+        let momentum_correction = 1.0 - BETA1.powi(self.steps);
+        let velocity_correction = 1.0 - BETA2.powi(self.steps);
+        for index in 0..Network::PARAMETERS {
+            let gradient = self.gradients[index];
+            self.momentum[index] = BETA1 * self.momentum[index] + (1.0 - BETA1) * gradient;
+            self.velocity[index] = BETA2 * self.velocity[index] + (1.0 - BETA2) * gradient * gradient;
+            let update = learning_rate * (self.momentum[index] / momentum_correction) / ((self.velocity[index] / velocity_correction).sqrt() + EPSILON);
+            let limit = if index < OUTPUT_WEIGHTS { MAX_FEATURE_WEIGHT } else { MAX_OUTPUT_WEIGHT };
+            self.weights[index] = (self.weights[index] - update).clamp(-limit, limit);
+        }
+        loss
+    }
+
+    /// The integer network of `nnue`, the accumulator is scaled by `QA` and the output layer by `QB`.
+    fn quantise(&self) -> Network {
+        let quantise = |weights: &[f32], scale: i32| weights.iter().map(|weight| (weight * scale as f32).round() as i16).collect::<Vec<i16>>();
+        Network {
+            feature_weights: quantise(&self.weights[..FEATURE_BIAS], QA),
+            feature_bias: quantise(&self.weights[FEATURE_BIAS..OUTPUT_WEIGHTS], QA),
+            output_weights: quantise(&self.weights[OUTPUT_WEIGHTS..OUTPUT_BIAS], QB),
+            output_bias: quantise(&self.weights[OUTPUT_BIAS..], QB)[0],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::gamestate::GameState;
+    use crate::nnue::{Accumulator, OUTPUT_SCALE};
+    use crate::texel::K;
+
+    use super::{Trainer, TrainingPosition, OUTPUT_BIAS, OUTPUT_WEIGHTS};
+
+    const FENS: [(&str, f32); 3] = [
+        ("r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR w KQkq - 4 4", 1.0),
+        ("6k1/5ppp/8/8/8/8/5PPP/R5K1 b - - 0 1", 0.0),
+        ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 0.5),
+    ];
+
+    #[test]
+    fn test_training_reduces_loss() {
+        let positions: Vec<TrainingPosition> = FENS.iter().map(|&(fen, result)| TrainingPosition::new(&GameState::new_from_fen(fen), result)).collect();
+        let mut trainer = Trainer::new(&mut StdRng::seed_from_u64(1), K as f32);
+        let first_loss = trainer.step(&positions, 0.001);
+        let mut loss = first_loss;
+        for _ in 0..50 {
+            loss = trainer.step(&positions, 0.001);
+        }
+        assert!(loss < first_loss / 2.0, "loss went from {first_loss} to {loss}");
+    }
+
+    #[test]
+    fn test_quantised_network_matches_float() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut trainer = Trainer::new(&mut rng, K as f32);
+        // Output weights of a trained size, the initial ones are too small to survive the rounding.
+        for weight in &mut trainer.weights[OUTPUT_WEIGHTS..OUTPUT_BIAS] {
+            *weight = rng.gen_range(-0.5..0.5);
+        }
+        let network = Box::leak(Box::new(trainer.quantise()));
+        for (fen, _) in FENS {
+            let state = GameState::new_from_fen(fen);
+            let float_eval = trainer.output(&trainer.hidden(&TrainingPosition::new(&state, 0.5))) * OUTPUT_SCALE as f32;
+            let mut accumulator = Accumulator::default();
+            accumulator.refresh_all(Some(network), &state.piece_boards);
+            let eval = network.evaluate(&accumulator, state.side_to_move());
+            assert!((eval as f32 - float_eval).abs() < 10.0 + float_eval.abs() / 20.0, "quantised {eval}, float {float_eval}");
+        }
+    }
+}
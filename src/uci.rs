@@ -8,8 +8,8 @@ use std::thread::JoinHandle;
 use std::time::Duration;
 use std::time::Instant;
 use crate::eval::EVAL_PARAMS;
+use crate::eval::EvalTrace;
 use crate::gamestate::BLACK;
-use crate::gamestate::KING;
 use crate::gamestate::PAWN;
 use crate::gamestate::WHITE;
 use crate::lockless::LockLessTransTable;
@@ -17,21 +17,42 @@ use crate::smpsearch::MAX_DEPTH;
 use crate::smpsearch::SearchProtocol;
 use crate::smpsearch::UciMode;
 use crate::smpsearch::search;
+use crate::smpsearch::SearchLimits;
+use crate::smpsearch::SEARCH_PARAMS;
+use crate::smpsearch::bench;
+use crate::timeman::TimeManager;
+use crate::pawnhash::{DEFAULT_PAWN_HASH_SIZE, PAWN_HASH_SIZE, MAX_PAWN_HASH_SIZE};
+use crate::nnue;
 
 use crate::gamestate::GameState;
 use crate::smpsearch::Eval;
-use crate::tt::TranspositionTable;
 use crate::r#move::Move;
 use std::io::stdin;
 
 const ENGINE_NAME: &str = "engine";
 const AUTHOR_NAME: &str = "418teapot";
+const BENCH_DEPTH: u8 = 10;
+const DEFAULT_MOVE_OVERHEAD: u64 = 30;
+const MAX_MOVE_OVERHEAD: u64 = 5000;
+const MAX_CONTEMPT: Eval = 100;
+
+/// Current values of the options announced by `cmd_uci`.
+pub struct UciOptions {
+    pub move_overhead: u64,
+}
+
+impl Default for UciOptions {
+    fn default() -> Self {
+        UciOptions { move_overhead: DEFAULT_MOVE_OVERHEAD }
+    }
+}
 
 pub fn uci_loop() {
     let mut gamestate = GameState::new_starting_pos();
     let mut search: Option<JoinHandle<(Move, Eval)>> = None;
     let stop_flag = Arc::new(SyncUnsafeCell::new(false));
     let trans_table = Arc::new(SyncUnsafeCell::new(LockLessTransTable::new()));
+    let mut options = UciOptions::default();
     loop {
         let mut input = String::new();
         stdin().read_line(&mut input).expect("Couldn't read string");
@@ -48,15 +69,16 @@ pub fn uci_loop() {
             "go" => {
                 if let Some(ref thread) = search {
                     if thread.is_finished() {
-                        search = Some(cmd_go(&parts[1..], gamestate.clone(), &stop_flag, &trans_table));
+                        search = Some(cmd_go(&parts[1..], gamestate.clone(), &stop_flag, &trans_table, &options));
                     }
                 } else {
                     unsafe {
                         *stop_flag.get() = true;
-                        search = Some(cmd_go(&parts[1..], gamestate.clone(), &stop_flag, &trans_table));
+                        search = Some(cmd_go(&parts[1..], gamestate.clone(), &stop_flag, &trans_table, &options));
                     }
                 }
             },
+            "setoption" => cmd_setoption(&parts[1..], &mut options),
             "quit" => return,
             "ucinewgame" => {
                 gamestate = GameState::new_starting_pos();
@@ -89,12 +111,19 @@ pub fn uci_loop() {
                     println!("Pawn Value: {}", EVAL_PARAMS.mg_piece_value[PAWN]);
                 }
             },
+            "eval" | "trace" => {
+                cmd_trace(&gamestate);
+            },
             "debugame" => {
                 run_debug_game(&gamestate);
             },
             "zobristhis" => {
                 zobrist_history(&gamestate);
             },
+            "bench" => {
+                let depth = parts.get(1).and_then(|depth| depth.parse::<usize>().ok()).map_or(BENCH_DEPTH, |depth| depth.clamp(1, MAX_DEPTH) as u8);
+                bench(depth);
+            },
             _ => println!("{}", cmd),
         }
     }
@@ -103,7 +132,7 @@ pub fn uci_loop() {
 pub fn run_debug_game(state: &GameState) {
     let mut clone_state = state.clone();
     while !clone_state.is_game_over() {
-        let best_move = search::<{ SearchProtocol::Uci(UciMode::Movetime) }>(1, Duration::from_secs(1), clone_state.clone(), Arc::new(SyncUnsafeCell::new(false)), 20, Arc::new(SyncUnsafeCell::new(LockLessTransTable::new())));
+        let best_move = search::<{ SearchProtocol::Uci(UciMode::Movetime) }>(1, SearchLimits::new(TimeManager::fixed(Duration::from_secs(1)), 20), clone_state.clone(), Arc::new(SyncUnsafeCell::new(false)), Arc::new(SyncUnsafeCell::new(LockLessTransTable::new())));
         clone_state.apply_legal_move(best_move.0);
         println!("{}", clone_state.static_eval());
         println!("{} ", best_move.0.to_algebraic());
@@ -115,10 +144,10 @@ pub fn zobrist_history(state: &GameState) {
     for history in state.history.iter() {
         println!("{} {}", history.r#move.to_algebraic(), history.zobrist.0)
     }
-    println!("Is Draw?: {}", state.has_repitition())
+    println!("Is Draw?: {}", state.is_threefold_repetition())
 }
 
-pub fn cmd_go(parts: &[&str], gamestate: GameState, stop_flag: &Arc<SyncUnsafeCell<bool>>, trans_table: &Arc<SyncUnsafeCell<LockLessTransTable>>) -> std::thread::JoinHandle<(Move, Eval)> {
+pub fn cmd_go(parts: &[&str], gamestate: GameState, stop_flag: &Arc<SyncUnsafeCell<bool>>, trans_table: &Arc<SyncUnsafeCell<LockLessTransTable>>, options: &UciOptions) -> std::thread::JoinHandle<(Move, Eval)> {
     let mut part_index = 0;
     let mut settings: HashMap<String, i64> = HashMap::new();
     let mut is_infinite = false;
@@ -133,25 +162,36 @@ pub fn cmd_go(parts: &[&str], gamestate: GameState, stop_flag: &Arc<SyncUnsafeCe
     }
     let wtime = *settings.get("wtime").unwrap_or(&0) as u64;
     let btime = *settings.get("btime").unwrap_or(&0) as u64;
-    let winc = *settings.get("winc").unwrap_or(&50) as u64;
-    let binc = *settings.get("binc").unwrap_or(&50) as u64;
+    let winc = *settings.get("winc").unwrap_or(&0) as u64;
+    let binc = *settings.get("binc").unwrap_or(&0) as u64;
+    let moves_to_go = settings.get("movestogo").map(|&moves| moves as u64);
+    // The limit is exclusive and has to fit a `u8`, out of range depths are clamped first.
+    let max_depth = settings.get("depth").map_or(MAX_DEPTH as u8, |&depth| depth.clamp(1, MAX_DEPTH as i64) as u8 + 1);
+    let max_nodes = settings.get("nodes").map_or(u64::MAX, |&nodes| nodes as u64);
+    let has_clock = settings.contains_key("wtime") || settings.contains_key("btime");
     let stop_flag_clone = Arc::clone(stop_flag);
     let trans_table_clone = Arc::clone(trans_table);
     unsafe {
         *stop_flag_clone.get() = false;
     }
-    let search = if is_infinite {
+    let search = if let Some(&movetime) = settings.get("movetime").filter(|_| !is_infinite) {
+        let time_manager = TimeManager::fixed(Duration::from_millis((movetime as u64).saturating_sub(options.move_overhead)));
+        let limits = SearchLimits { time_manager, max_depth, max_nodes };
         thread::spawn(move || {
-            search::<{ SearchProtocol::Uci(UciMode::Infinite) }>(1, Duration::from_micros(0), gamestate, stop_flag_clone, MAX_DEPTH as u8, trans_table_clone)
+            search::<{ SearchProtocol::Uci(UciMode::Movetime) }>(1, limits, gamestate, stop_flag_clone, trans_table_clone)
         })
-    } else if let Some(&movetime) = settings.get("movetime") {
+    } else if has_clock && !is_infinite {
+        let (time_left, increment) = if gamestate.side_to_move() == WHITE { (wtime, winc) } else { (btime, binc) };
+        let time_manager = TimeManager::from_clock(&gamestate, time_left, increment, moves_to_go, options.move_overhead);
+        let limits = SearchLimits { time_manager, max_depth, max_nodes };
         thread::spawn(move || {
-            search::<{ SearchProtocol::Uci(UciMode::Movetime) }>(1, Duration::from_millis(movetime as u64), gamestate, stop_flag_clone, MAX_DEPTH as u8, trans_table_clone)
+            search::<{ SearchProtocol::Uci(UciMode::Movetime) }>(1, limits, gamestate, stop_flag_clone, trans_table_clone)
         })
     } else {
-        let move_time = gamestate.calculate_movetime(wtime, btime, winc, binc);
+        // Infinite, depth and node limited searches run until a limit or `stop` ends them.
+        let limits = SearchLimits { time_manager: TimeManager::fixed(Duration::ZERO), max_depth, max_nodes };
         thread::spawn(move || {
-            search::<{ SearchProtocol::Uci(UciMode::Movetime) }>(1, Duration::from_millis(move_time), gamestate, stop_flag_clone, MAX_DEPTH as u8, trans_table_clone)
+            search::<{ SearchProtocol::Uci(UciMode::Infinite) }>(1, limits, gamestate, stop_flag_clone, trans_table_clone)
         })
     };
     
@@ -161,9 +201,72 @@ pub fn cmd_go(parts: &[&str], gamestate: GameState, stop_flag: &Arc<SyncUnsafeCe
 
 pub fn cmd_uci(_parts: &[&str]) {
     println!("id {} {}", ENGINE_NAME, AUTHOR_NAME);
+    println!("option name Move Overhead type spin default {} min 0 max {}", DEFAULT_MOVE_OVERHEAD, MAX_MOVE_OVERHEAD);
+    unsafe {
+        println!("option name Contempt type spin default {} min {} max {}", SEARCH_PARAMS.contempt, -MAX_CONTEMPT, MAX_CONTEMPT);
+        println!("option name Dynamic Contempt type check default {}", SEARCH_PARAMS.dynamic_contempt);
+        println!("option name PawnHash type spin default {} min 1 max {}", DEFAULT_PAWN_HASH_SIZE, MAX_PAWN_HASH_SIZE);
+    }
+    println!("option name EvalFile type string default <empty>");
     println!("uciok");
 }
 
+/// Handles `setoption name <name> [value <value>]`, option names may contain spaces.
+pub fn cmd_setoption(parts: &[&str], options: &mut UciOptions) {
+    if parts.first() != Some(&"name") {
+        return;
+    }
+    let value_index = parts.iter().position(|&part| part == "value").unwrap_or(parts.len());
+    let name = parts[1..value_index].join(" ");
+    let value = parts.get(value_index + 1..).map(|value| value.join(" ")).unwrap_or_default();
+    match name.to_lowercase().as_str() {
+        "move overhead" => {
+            if let Ok(move_overhead) = value.parse::<u64>() {
+                options.move_overhead = move_overhead.min(MAX_MOVE_OVERHEAD);
+            }
+        },
+        "contempt" => {
+            if let Ok(contempt) = value.parse::<Eval>() {
+                unsafe {
+                    SEARCH_PARAMS.contempt = contempt.clamp(-MAX_CONTEMPT, MAX_CONTEMPT);
+                }
+            }
+        },
+        "dynamic contempt" => {
+            if let Ok(dynamic_contempt) = value.parse::<bool>() {
+                unsafe {
+                    SEARCH_PARAMS.dynamic_contempt = dynamic_contempt;
+                }
+            }
+        },
+        "pawnhash" => {
+            if let Ok(size) = value.parse::<usize>() {
+                unsafe {
+                    PAWN_HASH_SIZE = size.clamp(1, MAX_PAWN_HASH_SIZE);
+                }
+            }
+        },
+        "evalfile" => {
+            match nnue::set_network(&value) {
+                Ok(()) if nnue::network().is_some() => println!("info string loaded network {}", value),
+                Ok(()) => println!("info string using the classical eval"),
+                Err(error) => println!("info string couldn't load network {}: {}, keeping the previous eval", value, error),
+            }
+        },
+        _ => println!("info string unknown option {}", name),
+    }
+}
+
+/// Prints every evaluation term of the current position.
+pub fn cmd_trace(state: &GameState) {
+    let mut trace = EvalTrace::default();
+    let eval = state.static_eval_traced(&mut trace);
+    trace.print(state.phase(), eval);
+    if let Some(network) = state.accumulator.network {
+        println!("NNUE eval (side to move): {}", network.evaluate(&state.accumulator, state.side_to_move()));
+    }
+}
+
 pub fn cmd_isready(_parts: &[&str]) {
     println!("readyok");
 }
@@ -219,25 +322,6 @@ pub fn algebraic_to_index(algebraic: &str) -> Option<usize> {
     }
 }
 
-pub fn extract_pv(state: &mut GameState, t_table: &TranspositionTable) -> String {
-    let mut pv_string = String::new();
-    
-    let mut copy_state = state.clone();
-
-    let mut depth = 0;
-    while let Some(entry) = t_table.probe(copy_state.zobrist) {
-        if depth > entry.depth {
-            break;
-        }
-        let pvmove = entry.best_move;
-        pv_string.push_str(&format!("{} ", pvmove.to_algebraic()));
-        copy_state.apply_legal_move(pvmove);
-        depth += 1;
-    }
-
-    pv_string
-}
-
 #[allow(dead_code)]
 pub fn perft_debug(state: &mut GameState, depth: u32) {
     assert!(depth > 0);
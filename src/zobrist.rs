@@ -1,5 +1,6 @@
-use crate::gamestate::{Piece, Side, NUM_OF_PIECES, NUM_OF_PLAYERS, BLACK};
+use crate::gamestate::{Piece, Side, NUM_OF_PIECES, NUM_OF_PLAYERS, BLACK, ROOK, KNIGHT, BISHOP, QUEEN, KING};
 use crate::bitboard::{Square, Bitboard, NUM_OF_SQUARES};
+use crate::movegen::{rook_move_bitboard, bishop_move_bitboard, queen_move_bitboard, knight_move_bitboard, KING_MOVES};
 use rand::Rng;
 
 #[derive(Default, Copy, Clone, PartialEq, Debug)]
@@ -56,6 +57,25 @@ impl ZobristHash {
     }
 }
 
+/// Slots of the cuckoo tables, the 3668 reversible non-pawn moves fit with room to spare.
+const CUCKOO_SIZE: usize = 8192;
+
+fn cuckoo_h1(key: u64) -> usize {
+    (key & (CUCKOO_SIZE as u64 - 1)) as usize
+}
+
+fn cuckoo_h2(key: u64) -> usize {
+    ((key >> 16) & (CUCKOO_SIZE as u64 - 1)) as usize
+}
+
+/// The reversible move whose hash difference is `key`, as a square pair that can be played in either direction.
+pub fn cuckoo_move(key: ZobristHash) -> Option<(Square, Square)> {
+    let (keys, moves) = &*CUCKOO_TABLES;
+    [cuckoo_h1(key.0), cuckoo_h2(key.0)].into_iter()
+        .find(|&slot| keys[slot] == key.0)
+        .map(|slot| moves[slot])
+}
+
 lazy_static! {
     static ref ZOBRIST_PIECES: [[[ZobristHash; NUM_OF_SQUARES]; NUM_OF_PIECES]; NUM_OF_PLAYERS] = {
         let mut rng = rand::thread_rng();
@@ -91,4 +111,37 @@ lazy_static! {
         }
         zobrist_en_passant_square
     };
-}
\ No newline at end of file
+
+    /// Hash differences of every non-pawn move on an empty board, cuckoo hashed for the upcoming repetition check.
+    static ref CUCKOO_TABLES: (Vec<u64>, Vec<(Square, Square)>) = {
+        let mut keys = vec![0; CUCKOO_SIZE];
+        let mut moves = vec![(0, 0); CUCKOO_SIZE];
+        for side in 0..NUM_OF_PLAYERS {
+            for piece in [ROOK, KNIGHT, BISHOP, QUEEN, KING] {
+                for from in 0..NUM_OF_SQUARES {
+                    let attacks = match piece {
+                        ROOK => rook_move_bitboard(from, Bitboard(0)),
+                        KNIGHT => knight_move_bitboard(from),
+                        BISHOP => bishop_move_bitboard(from, Bitboard(0)),
+                        QUEEN => queen_move_bitboard(from, Bitboard(0)),
+                        _ => KING_MOVES[from],
+                    };
+                    for to in attacks.into_iter().filter(|&to| to > from) {
+                        let mut key = ZOBRIST_PIECES[side][piece][from].0 ^ ZOBRIST_PIECES[side][piece][to].0 ^ ZOBRIST_BLACK_TO_MOVE.0;
+                        let mut r#move = (from, to);
+                        let mut slot = cuckoo_h1(key);
+                        loop {
+                            std::mem::swap(&mut keys[slot], &mut key);
+                            std::mem::swap(&mut moves[slot], &mut r#move);
+                            if key == 0 {
+                                break;
+                            }
+                            slot = if slot == cuckoo_h1(key) { cuckoo_h2(key) } else { cuckoo_h1(key) };
+                        }
+                    }
+                }
+            }
+        }
+        (keys, moves)
+    };
+}